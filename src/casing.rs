@@ -0,0 +1,50 @@
+use std::cmp::Ordering;
+
+use unicode_general_category::{get_general_category, GeneralCategory};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::{CaseFolding, Collation};
+
+/// The lowercase form of `ch` under `folding`.
+pub fn lowercase_char(ch: char, folding: CaseFolding) -> impl Iterator<Item = char> {
+    let turkish = match (folding, ch) {
+        (CaseFolding::Turkish, 'I') => Some('ı'),
+        (CaseFolding::Turkish, 'İ') => Some('i'),
+        _ => None,
+    };
+
+    turkish
+        .into_iter()
+        .chain(turkish.is_none().then(|| ch.to_lowercase()).into_iter().flatten())
+}
+
+/// The lowercase form of `s` under `folding`.
+pub fn lowercase(s: &str, folding: CaseFolding) -> String {
+    s.chars().flat_map(|ch| lowercase_char(ch, folding)).collect()
+}
+
+/// Whether `s` starts with `prefix`, ignoring case under `folding`.
+pub fn starts_with_ignore_case(s: &str, prefix: &str, folding: CaseFolding) -> bool {
+    let mut s = s.chars().flat_map(|ch| lowercase_char(ch, folding));
+
+    prefix
+        .chars()
+        .flat_map(|ch| lowercase_char(ch, folding))
+        .all(|ch| s.next() == Some(ch))
+}
+
+/// Compare `a` and `b` for sorting under `collation`.
+pub fn collate(a: &str, b: &str, collation: Collation) -> Ordering {
+    match collation {
+        Collation::Codepoint => a.cmp(b),
+        Collation::Unicode => {
+            let key = |s: &str| {
+                s.nfd()
+                    .filter(|&ch| get_general_category(ch) != GeneralCategory::NonspacingMark)
+                    .flat_map(char::to_lowercase)
+                    .collect::<String>()
+            };
+            key(a).cmp(&key(b)).then_with(|| a.cmp(b))
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use tower_lsp::lsp_types::{Color, ColorInformation, ColorPresentation, Position, Range, TextEdit};
+
+use crate::{character_len, lines_with_offsets};
+
+/// Find the hex color tokens (`#rgb`, `#rgba`, `#rrggbb` and `#rrggbbaa`) in `text`.
+pub fn document_colors(text: &str) -> Vec<ColorInformation> {
+    let mut colors = Vec::new();
+
+    for (line_number, (_, line)) in lines_with_offsets(text).enumerate() {
+        for (start, _) in line.match_indices('#') {
+            // `&#123;` is an HTML entity, not a color
+            let preceded_by_word = line[..start]
+                .chars()
+                .next_back()
+                .is_some_and(|ch| ch.is_alphanumeric() || ch == '&');
+            let digits = &line[start + 1..];
+            let len = digits
+                .find(|ch: char| !ch.is_ascii_hexdigit())
+                .unwrap_or(digits.len());
+            let followed_by_word = digits[len..]
+                .chars()
+                .next()
+                .is_some_and(|ch| ch.is_alphanumeric() || ch == '_');
+
+            if preceded_by_word || followed_by_word {
+                continue;
+            }
+
+            let Some(color) = parse_hex_color(&digits[..len]) else {
+                continue;
+            };
+
+            let character = character_len(&line[..start]);
+            let line_number = line_number as u32;
+            colors.push(ColorInformation {
+                range: Range::new(
+                    Position::new(line_number, character),
+                    Position::new(line_number, character + 1 + len as u32),
+                ),
+                color,
+            });
+        }
+    }
+
+    colors
+}
+
+/// The ways `color` can be written in place of `range`.
+pub fn color_presentations(color: Color, range: Range) -> Vec<ColorPresentation> {
+    let [red, green, blue, alpha] = [color.red, color.green, color.blue, color.alpha]
+        .map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+    let labels = if alpha == u8::MAX {
+        vec![format!("#{red:02x}{green:02x}{blue:02x}"), format!("rgb({red}, {green}, {blue})")]
+    } else {
+        vec![
+            format!("#{red:02x}{green:02x}{blue:02x}{alpha:02x}"),
+            format!("rgba({red}, {green}, {blue}, {:.2})", color.alpha),
+        ]
+    };
+
+    labels
+        .into_iter()
+        .map(|label| ColorPresentation {
+            text_edit: Some(TextEdit::new(range, label.clone())),
+            label,
+            additional_text_edits: None,
+        })
+        .collect()
+}
+
+/// Parse the digits of a hex color, without the leading `#`.
+fn parse_hex_color(digits: &str) -> Option<Color> {
+    let channel = |hex: &str| u8::from_str_radix(hex, 16).ok().map(|c| c as f32 / 255.0);
+    // Short forms repeat every digit: `#f80` is `#ff8800`
+    let short = |i: usize| channel(&digits[i..=i].repeat(2));
+    let long = |i: usize| channel(&digits[i * 2..i * 2 + 2]);
+
+    let (red, green, blue, alpha) = match digits.len() {
+        3 => (short(0)?, short(1)?, short(2)?, 1.0),
+        4 => (short(0)?, short(1)?, short(2)?, short(3)?),
+        6 => (long(0)?, long(1)?, long(2)?, 1.0),
+        8 => (long(0)?, long(1)?, long(2)?, long(3)?),
+        _ => return None,
+    };
+
+    Some(Color { red, green, blue, alpha })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_entities_and_words_are_not_colors() {
+        let colors = document_colors("&#123; a#fff #abc;\n#12345 #ff8800");
+
+        let ranges = colors.iter().map(|color| color.range).collect::<Vec<_>>();
+        assert_eq!(
+            ranges,
+            [
+                Range::new(Position::new(0, 13), Position::new(0, 17)),
+                Range::new(Position::new(1, 7), Position::new(1, 14)),
+            ]
+        );
+        assert_eq!(
+            colors[1].color,
+            Color {
+                red: 1.0,
+                green: 8.0 * 17.0 / 255.0,
+                blue: 0.0,
+                alpha: 1.0
+            }
+        );
+    }
+}
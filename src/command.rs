@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Range, TextDocumentIdentifier, Url};
+
+use crate::{
+    config::TokenizerConfig,
+    get_char_index_from_position, get_position_from_char_index, split_indices,
+    text::{categorize_char_with, CharCategory},
+    tokenizer::Tokenizer,
+    PositionEncoding,
+};
+
+/// Report how a range of a document is split into tokens.
+pub const TOKENIZE: &str = "buffer-ls.tokenize";
+
+/// Forget a document, so the client sends it again when reopening it. The argument is a
+/// `TextDocumentIdentifier`.
+pub const RESET_DOCUMENT: &str = "buffer-ls.resetDocument";
+
+/// Index a document again from scratch. The argument is a `TextDocumentIdentifier`; every
+/// document is indexed again without one.
+pub const REBUILD_INDEX: &str = "buffer-ls.rebuildIndex";
+
+/// Report the selection of a document, whose words `excludeSelection` leaves out of completions.
+/// The protocol does not tell servers about selections, so clients have to.
+pub const SET_SELECTION: &str = "buffer-ls.setSelection";
+
+/// Return the labels of the completion candidates for a prefix in a document, ranked, without
+/// going through a cursor position.
+pub const COMPLETE: &str = "buffer-ls.complete";
+
+/// List the documents the server tracks, for debugging.
+pub const DOCUMENTS: &str = "buffer-ls.documents";
+
+/// Commands advertised in `executeCommandProvider`.
+pub const COMMANDS: &[&str] =
+    &[TOKENIZE, RESET_DOCUMENT, REBUILD_INDEX, SET_SELECTION, COMPLETE, DOCUMENTS];
+
+/// Argument of [`TOKENIZE`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenizeArguments {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+/// Argument of [`SET_SELECTION`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetSelectionArguments {
+    pub text_document: TextDocumentIdentifier,
+    /// The selection, or nothing when there is none.
+    pub range: Option<Range>,
+}
+
+/// Argument of [`COMPLETE`].
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompleteArguments {
+    pub text_document: TextDocumentIdentifier,
+    pub prefix: String,
+}
+
+/// A document listed by [`DOCUMENTS`].
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentInfo<'a> {
+    pub uri: &'a Url,
+    pub version: i32,
+    pub language_id: &'a str,
+    /// Number of words in the document, counting repeated words every time.
+    pub word_count: usize,
+    /// Whether the client still has the document open, rather than it being kept as a cache.
+    pub open: bool,
+}
+
+/// A token of the range passed to [`TOKENIZE`].
+#[derive(Debug, Serialize)]
+pub struct Token<'a> {
+    pub text: &'a str,
+    /// Category of the last character, the one a sigil token is classified by.
+    pub category: CharCategory,
+    pub range: Range,
+}
+
+/// Split the text of `range` in `text` into tokens, the way completion sees them.
+pub fn tokenize<'a>(
+    text: &'a str,
+    range: Range,
+    tokenizer: &dyn Tokenizer,
+    config: &TokenizerConfig,
+) -> Vec<Token<'a>> {
+    let start = get_char_index_from_position(text, range.start, PositionEncoding::Utf16);
+    let end = get_char_index_from_position(text, range.end, PositionEncoding::Utf16).max(start);
+
+    split_indices(&text[start..end], tokenizer, config)
+        .map(|(offset, word)| Token {
+            text: word,
+            category: word
+                .chars()
+                .last()
+                .map_or(CharCategory::Unknown, |ch| categorize_char_with(ch, config)),
+            range: Range::new(
+                get_position_from_char_index(text, start + offset, PositionEncoding::Utf16),
+                get_position_from_char_index(
+                    text,
+                    start + offset + word.len(),
+                    PositionEncoding::Utf16,
+                ),
+            ),
+        })
+        .collect()
+}
@@ -0,0 +1,1868 @@
+//! The completion engine, independent of the language server transport.
+//!
+//! [`complete`] offers the words of a text the way the server offers the words of a buffer, so
+//! tools embedding the crate complete text the same way without running a server.
+
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde_json::{json, Map, Value};
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionItemTag,
+    CompletionList, CompletionTextEdit, InsertReplaceEdit, Position, Range, TextEdit,
+};
+
+use crate::{
+    casing::{lowercase, starts_with_ignore_case},
+    character_len,
+    config::{
+        CaseFolding, Config, IndexCase, InsertMode, Matching, ProximityMetric, Ranking,
+        RegionRules, Scope,
+    },
+    document::Document,
+    find_word_before_cursor, get_byte_offset_in_line, get_char_index_from_position, get_line,
+    index::Occurrences,
+    lines_with_offsets, split_positions,
+    text::{categorize_char_with, CharCategory},
+    tokenizer::{CategoryTokenizer, Tokenizer},
+    word_len, PositionEncoding,
+};
+
+/// Completion features the client advertised support for in `initialize`.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CompletionSupport {
+    /// `completionItem.labelDetailsSupport`
+    pub label_details: bool,
+    /// `completionItem.insertReplaceSupport`
+    pub insert_replace: bool,
+    /// `completionItem.tagSupport` includes `Deprecated`
+    pub deprecated_tag: bool,
+    /// `completionItem.commitCharactersSupport`
+    pub commit_characters: bool,
+    /// Maximum number of items the client asked for, if it told us.
+    pub max_items: Option<usize>,
+    /// `completionList.itemDefaults`
+    pub item_defaults: ItemDefaultsSupport,
+}
+
+/// The `itemDefaults` of a completion list the client supports, among those the server sets.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ItemDefaultsSupport {
+    pub commit_characters: bool,
+    pub edit_range: bool,
+    pub insert_text_format: bool,
+}
+
+impl ItemDefaultsSupport {
+    /// Support for the `itemDefaults` properties the client listed by name.
+    pub fn new(names: &[String]) -> Self {
+        let supports = |name: &str| names.iter().any(|supported| supported == name);
+
+        Self {
+            commit_characters: supports("commitCharacters"),
+            edit_range: supports("editRange"),
+            insert_text_format: supports("insertTextFormat"),
+        }
+    }
+}
+
+/// Number of completion results kept per document.
+const COMPLETION_CACHE_SIZE: usize = 4;
+
+/// How many times more frequent than a word one edit away from it a word must be for the rarer
+/// one to be taken for a misspelling, with `typoPenalty`.
+const TYPO_FREQUENCY_RATIO: usize = 4;
+
+/// The last few completion results of a document, so a client asking again for the same
+/// completion does not recompute it. Must be cleared whenever the configuration changes, and
+/// [invalidated](Self::invalidate) whenever the document does.
+#[derive(Debug, Default)]
+pub(crate) struct CompletionCache {
+    entries: VecDeque<(CacheKey, CompletionList)>,
+}
+
+/// The document version is deliberately not part of the key: every change goes through
+/// [`CompletionCache::invalidate`], which keeps exactly the entries the change cannot affect, so
+/// they stay valid across versions.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct CacheKey {
+    /// The edit ranges of the items depend on the cursor, not only on the prefix.
+    pub position: Position,
+    /// Text of the line of the cursor, which the items also depend on, unlike the rest of the
+    /// document.
+    pub line: String,
+    pub prefix: String,
+}
+
+impl CompletionCache {
+    pub fn get(&self, key: &CacheKey) -> Option<&CompletionList> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, list)| list)
+    }
+
+    pub fn insert(&mut self, key: CacheKey, list: CompletionList) {
+        if self.entries.len() == COMPLETION_CACHE_SIZE {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back((key, list));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drop the entries an edit may have changed, given the words of the lines it touched,
+    /// before and after it: those whose prefix matches one of `edited`. Results depending on
+    /// more than the words matching their prefix are all dropped.
+    pub fn invalidate(&mut self, edited: &HashSet<String>, config: &Config, language_id: &str) {
+        if !depends_on_matches_only(config, language_id) {
+            self.clear();
+            return;
+        }
+
+        let ignore_case = Some(config.case_folding);
+        self.entries.retain(|(key, _)| {
+            // Nothing else is completed after a non-word, see `complete`
+            let is_word = key.prefix.chars().last().is_some_and(|ch| {
+                categorize_char_with(ch, &config.tokenizer) == CharCategory::Word
+            });
+
+            is_word
+                && edited.iter().all(|word| {
+                    match_candidate(word, &key.prefix, config.matching, ignore_case).is_none()
+                })
+        });
+    }
+}
+
+/// Whether the completions of a prefix only depend on the words matching it and on the line of
+/// the cursor, so that an edit touching neither leaves them as they were.
+fn depends_on_matches_only(config: &Config, language_id: &str) -> bool {
+    config.matching != Matching::None
+        && config.scope == Scope::Buffer
+        && config.ranking != Ranking::Proximity
+        && config.recency_weight == 0.0
+        && config.punctuation_context_weight == 0.0
+        && !config.exact_word_completion
+        && !config.typo_penalty
+        && !config.acronym_expansions
+        && !config.annotation_completion
+        && !config.fold_plurals
+        && config.deprecated_markers.is_empty()
+        && !config.closing_keywords.contains_key(language_id)
+        && !config.ignored_regions.contains_key(language_id)
+}
+
+/// Another source of completion candidates besides the buffer itself.
+pub(crate) struct Source<'a> {
+    /// Short name of the source, shown next to the candidates it contributes.
+    pub name: &'a str,
+    pub words: Words<'a>,
+}
+
+/// What a [`Source`] contributes.
+pub(crate) enum Words<'a> {
+    /// The text of another document, split into words like the buffer.
+    Text(&'a str),
+    /// A list of words, ranked below the words found in documents.
+    List(&'a [String]),
+}
+
+/// Compute the completion items for the cursor at `position` in `text`, best first, as the
+/// server would offer them in a document of that text to a client without optional features.
+///
+/// ```
+/// use buffer_language_server::{completion::complete, config::Config};
+/// use tower_lsp::lsp_types::Position;
+///
+/// let items = complete("hello help\nhe", Position::new(1, 2), &Config::default());
+/// let labels = items.iter().map(|item| item.label.as_str()).collect::<Vec<_>>();
+/// assert_eq!(labels, ["hello", "help"]);
+/// ```
+pub fn complete(text: &str, position: Position, config: &Config) -> Vec<CompletionItem> {
+    let document = Document::new(
+        text.to_string(),
+        0,
+        "plaintext".to_string(),
+        true,
+        &CategoryTokenizer,
+        config,
+    );
+
+    complete_document(
+        &document,
+        position,
+        &CategoryTokenizer,
+        config,
+        CompletionSupport::default(),
+        &[],
+        &AtomicBool::new(false),
+    )
+    .map(|list| list.items)
+    .unwrap_or_default()
+}
+
+/// Like [`complete`], for the cursor at `position` in `document`, also offering the words of
+/// `others`.
+///
+/// This is the transport-independent core of `textDocument/completion`; the language server
+/// handler only locks the shared state and wraps the result.
+///
+/// The list is truncated to the smaller of `maxCompletionItems` and the limit signalled by the
+/// client, and marked incomplete when that happens so the client asks again as the user types.
+///
+/// Returns `None` as soon as `cancelled` is set, for requests the client no longer needs.
+pub(crate) fn complete_document(
+    document: &Document,
+    position: Position,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+    support: CompletionSupport,
+    others: &[Source],
+    cancelled: &AtomicBool,
+) -> Option<CompletionList> {
+    let (text, language_id) = (document.text.as_str(), document.language_id.as_str());
+    let current_word = find_word_before_cursor(text, position, tokenizer, &config.tokenizer);
+    let prefix_category = current_word
+        .chars()
+        .last()
+        .map(|ch| categorize_char_with(ch, &config.tokenizer));
+    let prefix = match prefix_category {
+        Some(CharCategory::Word) => current_word.as_str(),
+        Some(CharCategory::Punctuation) if config.punctuation_words => current_word.as_str(),
+        _ => "",
+    };
+    let prefix_start =
+        Position::new(position.line, position.character.saturating_sub(character_len(prefix)));
+    // The rest of the word when the cursor is in the middle of it
+    let line = get_line(text, position.line as usize).unwrap_or_default();
+    let after_cursor =
+        &line[get_byte_offset_in_line(line, position.character, PositionEncoding::Utf16)..];
+    let suffix_end = after_cursor
+        .char_indices()
+        .find(|&(_, ch)| {
+            prefix.is_empty()
+                || Some(categorize_char_with(ch, &config.tokenizer)) != prefix_category
+        })
+        .map_or(after_cursor.len(), |(end, _)| end);
+    let (suffix, after_word) = after_cursor.split_at(suffix_end);
+    let suffix_len = character_len(suffix);
+    // Completing again in the middle of a word must not offer the word itself, which inserting
+    // before the rest of it would duplicate
+    let word_at_cursor = format!("{prefix}{suffix}");
+    let next_punctuation = after_word
+        .chars()
+        .next()
+        .filter(|&ch| categorize_char_with(ch, &config.tokenizer) == CharCategory::Punctuation);
+    let insert_range = Range::new(prefix_start, position);
+    let replace_range =
+        Range::new(prefix_start, Position::new(position.line, position.character + suffix_len));
+
+    // Numbers are never worth completing inside hex dumps and numeric tables
+    if config.max_numeric_run.is_some_and(|max| {
+        let cursor = get_byte_offset_in_line(line, position.character, PositionEncoding::Utf16);
+        numeric_run_len(line, cursor) >= max
+    }) {
+        return Some(CompletionList { is_incomplete: false, items: Vec::new() });
+    }
+
+    let (mut candidates, is_incomplete) = ranked_candidates(
+        document,
+        Query {
+            prefix,
+            current_word: &current_word,
+            word_at_cursor: &word_at_cursor,
+            line,
+            position,
+            next_punctuation,
+        },
+        tokenizer,
+        config,
+        support,
+        others,
+        cancelled,
+    )?;
+
+    // Every item repeats the same ranges, which `with_item_defaults` sends once as the
+    // `editRange` default to the clients supporting it. For the others, sending a single range
+    // when there is no rest of the word to replace at least keeps each item small.
+    let text_edit = |new_text: &str| {
+        if support.insert_replace && insert_range != replace_range {
+            CompletionTextEdit::InsertAndReplace(InsertReplaceEdit {
+                new_text: new_text.to_string(),
+                insert: insert_range,
+                replace: replace_range,
+            })
+        } else {
+            let range = match config.insert_mode {
+                InsertMode::Insert => insert_range,
+                InsertMode::Replace => replace_range,
+            };
+            CompletionTextEdit::Edit(TextEdit::new(range, new_text.to_string()))
+        }
+    };
+
+    let mut items = Vec::new();
+    if let Some(closer) = config
+        .closing_keywords
+        .get(language_id)
+        .and_then(|pairs| {
+            let before = get_char_index_from_position(text, prefix_start, PositionEncoding::Utf16);
+            find_unclosed(&text[..before], pairs, tokenizer, config)
+        })
+        .filter(|closer| closer.starts_with(prefix))
+    {
+        candidates.retain(|(candidate, _)| candidate.word != closer);
+        items.push(CompletionItem {
+            label: closer.to_string(),
+            kind: Some(CompletionItemKind::KEYWORD),
+            text_edit: Some(text_edit(closer)),
+            sort_text: Some(format!("{:08}", 0)),
+            preselect: Some(true),
+            ..CompletionItem::default()
+        });
+    }
+    let closers = items.len();
+    if config.acronym_expansions && !prefix.is_empty() {
+        items.extend(
+            find_acronyms(text)
+                .into_iter()
+                .filter(|(acronym, _)| acronym.starts_with(prefix))
+                .enumerate()
+                .map(|(rank, (acronym, expansion))| CompletionItem {
+                    label: expansion.to_string(),
+                    label_details: support.label_details.then(|| CompletionItemLabelDetails {
+                        detail: None,
+                        description: Some(acronym.to_string()),
+                    }),
+                    kind: Some(CompletionItemKind::TEXT),
+                    // The client would drop an expansion that does not start with the acronym
+                    filter_text: Some(prefix.to_string()),
+                    text_edit: Some(text_edit(expansion)),
+                    sort_text: Some(format!("{:08}", closers + rank)),
+                    ..CompletionItem::default()
+                }),
+        );
+    }
+    let expansions = items.len();
+    if let Some(typed) = config
+        .annotation_completion
+        .then(|| {
+            let cursor = get_byte_offset_in_line(line, position.character, PositionEncoding::Utf16);
+            annotation(&line[..cursor], &config.annotation_markers)
+        })
+        .flatten()
+    {
+        let start = Position::new(position.line, position.character - character_len(typed));
+        items.extend(
+            find_annotations(text, &config.annotation_markers, position.line)
+                .into_iter()
+                .filter(|annotation| annotation.starts_with(typed) && *annotation != typed)
+                .enumerate()
+                .map(|(rank, annotation)| CompletionItem {
+                    label: annotation.to_string(),
+                    kind: Some(CompletionItemKind::TEXT),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                        Range::new(start, position),
+                        annotation.to_string(),
+                    ))),
+                    sort_text: Some(format!("{:08}", expansions + rank)),
+                    ..CompletionItem::default()
+                }),
+        );
+    }
+    let annotations = items.len();
+
+    items.extend(candidates.into_iter().enumerate().map(
+        |(
+            rank,
+            (
+                Candidate {
+                    word,
+                    first_occurrence,
+                    count,
+                    stale,
+                    source,
+                    subword_start,
+                    ..
+                },
+                filter_on_prefix,
+            ),
+        )| {
+            let description = match source.checked_sub(1).map(|other| &others[other]) {
+                Some(Source { name, words: Words::List(_) }) => name.to_string(),
+                Some(Source { name, words: Words::Text(_) }) => format!("{count}× {name}"),
+                None => format!("{count}×"),
+            };
+
+            CompletionItem {
+                label: word.to_string(),
+                // Show where the prefix matched, as the client highlights its own, failed match
+                label_details: support.label_details.then(|| CompletionItemLabelDetails {
+                    detail: subword_start.map(|start| format!(" …{}", &word[start..])),
+                    description: Some(description),
+                }),
+                detail: None,
+                kind: Some(CompletionItemKind::TEXT),
+                // The client filters on its own and would drop a candidate that only matches in
+                // the middle or ignoring case, or a correction, so make it filter on the prefix
+                // itself.
+                filter_text: filter_on_prefix.then(|| prefix.to_string()),
+                text_edit: Some(text_edit(word)),
+                data: first_occurrence.and_then(|range| serde_json::to_value(range).ok()),
+                tags: (stale && support.deprecated_tag)
+                    .then(|| vec![CompletionItemTag::DEPRECATED]),
+                deprecated: (stale && !support.deprecated_tag).then_some(true),
+                // Clients sort by label unless told otherwise
+                sort_text: Some(format!("{:08}", annotations + rank)),
+                ..CompletionItem::default()
+            }
+        },
+    ));
+
+    // Replacing only what was typed with itself leaves the rest of the word alone
+    if config.include_prefix && !prefix.is_empty() && items.iter().all(|item| item.label != prefix)
+    {
+        items.push(CompletionItem {
+            label: prefix.to_string(),
+            kind: Some(CompletionItemKind::TEXT),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                insert_range,
+                prefix.to_string(),
+            ))),
+            sort_text: Some(format!("{:08}", items.len())),
+            ..CompletionItem::default()
+        });
+    }
+
+    Some(CompletionList { is_incomplete, items })
+}
+
+/// Move the properties every item of the serialized completion `list` shares to its
+/// `itemDefaults`, as far as `support` allows, so they are sent once. Other responses, such as
+/// a bare array of items, are returned unchanged.
+///
+/// A property only becomes a default when every item has the same value of it, since the
+/// default also applies to the items without one.
+pub(crate) fn with_item_defaults(mut list: Value, support: ItemDefaultsSupport) -> Value {
+    let Some(items) = list.get_mut("items").and_then(Value::as_array_mut) else {
+        return list;
+    };
+
+    let mut defaults = Map::new();
+    for (supported, property) in [
+        (support.commit_characters, "commitCharacters"),
+        (support.insert_text_format, "insertTextFormat"),
+    ] {
+        if let Some(shared) = supported.then(|| take_shared(items, property)).flatten() {
+            defaults.insert(property.to_string(), shared);
+        }
+    }
+
+    if let Some(range) = support.edit_range.then(|| take_shared_edit_range(items)).flatten() {
+        defaults.insert("editRange".to_string(), range);
+    }
+
+    if !defaults.is_empty() {
+        list["itemDefaults"] = Value::Object(defaults);
+    }
+    list
+}
+
+/// Like [`take_shared`], for the range of the `textEdit` of every item, which is then replaced by
+/// its `textEditText`, or nothing when that is the label.
+///
+/// Returns the range, or the insert and replace ranges, as an `editRange` default.
+fn take_shared_edit_range(items: &mut [Value]) -> Option<Value> {
+    let range = |item: &Value| {
+        let edit = item.get("textEdit")?;
+        match edit.get("range") {
+            Some(range) => Some(range.clone()),
+            None => Some(json!({ "insert": edit.get("insert")?, "replace": edit.get("replace")? })),
+        }
+    };
+    let shared = range(items.first()?)?;
+    if items.iter().any(|item| range(item).as_ref() != Some(&shared)) {
+        return None;
+    }
+
+    for item in items.iter_mut().filter_map(Value::as_object_mut) {
+        let Some(Value::Object(mut edit)) = item.remove("textEdit") else {
+            continue;
+        };
+        if let Some(new_text) =
+            edit.remove("newText").filter(|text| item.get("label") != Some(text))
+        {
+            item.insert("textEditText".to_string(), new_text);
+        }
+    }
+    Some(shared)
+}
+
+/// Remove `property` from every item and return its value, when they all have the same.
+fn take_shared(items: &mut [Value], property: &str) -> Option<Value> {
+    let shared = items.first()?.get(property)?.clone();
+    if items.iter().any(|item| item.get(property) != Some(&shared)) {
+        return None;
+    }
+
+    for item in items.iter_mut().filter_map(Value::as_object_mut) {
+        item.remove(property);
+    }
+    Some(shared)
+}
+
+/// The labels of the candidates for `prefix` in `document`, ranked as completion would offer
+/// them after typing `prefix` on a line of its own. Candidates are taken from the whole document
+/// whatever the configured [`Scope`], and filtered on `prefix` even with [`Matching::None`], as
+/// there is no client to do it.
+pub(crate) fn complete_prefix(
+    document: &Document,
+    prefix: &str,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+    support: CompletionSupport,
+    others: &[Source],
+) -> Vec<String> {
+    let matching = match config.matching {
+        Matching::None => Matching::Prefix,
+        matching => matching,
+    };
+    let config = Config { scope: Scope::Buffer, matching, ..config.clone() };
+    let query = Query {
+        prefix,
+        current_word: prefix,
+        word_at_cursor: prefix,
+        line: "",
+        position: Position::new(0, 0),
+        next_punctuation: None,
+    };
+
+    ranked_candidates(document, query, tokenizer, &config, support, others, &AtomicBool::new(false))
+        .map(|(candidates, _)| {
+            candidates
+                .into_iter()
+                .map(|(candidate, _)| candidate.word.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// What is being completed.
+struct Query<'q> {
+    prefix: &'q str,
+    /// The token before the cursor, which is not offered.
+    current_word: &'q str,
+    /// The whole word the cursor is in, rest after the cursor included, which is not offered
+    /// either.
+    word_at_cursor: &'q str,
+    /// The line of the cursor.
+    line: &'q str,
+    position: Position,
+    /// The punctuation right after the word of the cursor, if any.
+    next_punctuation: Option<char>,
+}
+
+/// The candidates for `query`, filtered, ranked and truncated, with whether some were left out
+/// by the limits. Returns `None` as soon as `cancelled` is set.
+fn ranked_candidates<'a>(
+    document: &'a Document,
+    query: Query,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+    support: CompletionSupport,
+    others: &[Source<'a>],
+    cancelled: &AtomicBool,
+) -> Option<(Vec<(Candidate<'a>, bool)>, bool)> {
+    let Query { prefix, current_word, word_at_cursor, line, .. } = query;
+
+    let current_line_words = if config.exclude_current_line {
+        tokenizer.split(line, &config.tokenizer).into_iter().collect()
+    } else {
+        HashSet::new()
+    };
+
+    // Rules out most words of the buffer without computing their edit distance
+    let near = config
+        .exact_word_completion
+        .then(|| document.occurrences.as_ref()?.near(prefix, config.max_edit_distance))
+        .flatten();
+
+    let mut candidates =
+        collect_candidates(document, &query, tokenizer, config, others, cancelled)?
+            .into_iter()
+            .filter_map(|mut candidate| {
+                let is_blank = candidate.word.chars().next().is_some_and(|ch| {
+                    match categorize_char_with(ch, &config.tokenizer) {
+                        CharCategory::Whitespace | CharCategory::Eol => true,
+                        CharCategory::Punctuation => {
+                            !config.punctuation_words
+                                || word_len(candidate.word, config.length_unit) < 2
+                        }
+                        _ => false,
+                    }
+                });
+                let ignore_case =
+                    (config.index_case == IndexCase::Folded).then_some(config.case_folding);
+                let is_current = |word: &str| {
+                    [current_word, word_at_cursor].into_iter().any(|current| {
+                        match config.index_case {
+                            IndexCase::Sensitive => word == current,
+                            IndexCase::Folded => {
+                                lowercase(word, config.case_folding)
+                                    == lowercase(current, config.case_folding)
+                            }
+                        }
+                    })
+                };
+                // Of the singular and plural, offer the one that was not typed and matches
+                if let Some(other) = candidate.other_form {
+                    if is_current(candidate.word)
+                        || match_candidate(candidate.word, prefix, config.matching, ignore_case)
+                            .is_none()
+                    {
+                        candidate.word = other;
+                    }
+                }
+                let is_current_word = is_current(candidate.word);
+                // Wordlist entries are not counted, so they are kept regardless
+                let is_rare = !candidate.listed && candidate.count < config.min_occurrences;
+                let is_short = !config.min_length_per_category.is_empty()
+                    && dominant_category(candidate.word, config)
+                        .and_then(|category| config.min_length_per_category.get(&category))
+                        .is_some_and(|&min| word_len(candidate.word, config.length_unit) < min);
+                if is_current_word
+                    || is_blank
+                    || is_rare
+                    || is_short
+                    || current_line_words.contains(candidate.word)
+                    || config.exclude_selection && document.selected_words.contains(candidate.word)
+                {
+                    return None;
+                }
+
+                if config.exact_word_completion {
+                    if prefix.is_empty() {
+                        return None;
+                    }
+                    if candidate.source == 0
+                        && near.as_ref().is_some_and(|near| !near.contains(candidate.word))
+                    {
+                        return None;
+                    }
+                    candidate.distance =
+                        edit_distance(candidate.word, prefix, config.max_edit_distance)?;
+                    if similarity(candidate.word, prefix, candidate.distance)
+                        < config.min_fuzzy_score
+                    {
+                        return None;
+                    }
+                    // A correction does not start with what was typed
+                    return Some((candidate, true));
+                }
+
+                // The client would drop what does not start with the prefix as typed
+                let filter_on_prefix =
+                    match match_candidate(candidate.word, prefix, config.matching, ignore_case)? {
+                        Match::Word => {
+                            config.matching != Matching::None && !candidate.word.starts_with(prefix)
+                        }
+                        Match::Subword(start) => {
+                            candidate.subword_start = Some(start);
+                            true
+                        }
+                    };
+
+                Some((candidate, filter_on_prefix))
+            })
+            .collect::<Vec<_>>();
+
+    if config.typo_penalty {
+        mark_likely_typos(&mut candidates, prefix);
+    }
+    rank(&mut candidates, config, document);
+    if config.prefer_exact_case && !prefix.is_empty() {
+        prefer_exact_case(&mut candidates, prefix, config.case_folding);
+    }
+
+    let mut is_incomplete = false;
+    if !config.max_items_per_category.is_empty() {
+        let mut counts = HashMap::<CharCategory, usize>::new();
+        candidates.retain(|(candidate, _)| {
+            let Some(category) = candidate
+                .word
+                .chars()
+                .next()
+                .map(|ch| categorize_char_with(ch, &config.tokenizer))
+            else {
+                return true;
+            };
+            let count = counts.entry(category).or_default();
+            *count += 1;
+            let kept = config
+                .max_items_per_category
+                .get(&category)
+                .is_none_or(|&max| *count <= max);
+            is_incomplete |= !kept;
+            kept
+        });
+    }
+
+    let limit = [config.max_completion_items, support.max_items]
+        .into_iter()
+        .flatten()
+        .min();
+    is_incomplete |= limit.is_some_and(|limit| candidates.len() > limit);
+    if let Some(limit) = limit {
+        candidates.truncate(limit);
+    }
+
+    Some((candidates, is_incomplete))
+}
+
+/// The category most characters of `word` fall in, the first of them on a tie.
+fn dominant_category(word: &str, config: &Config) -> Option<CharCategory> {
+    let mut counts = Vec::<(CharCategory, usize)>::new();
+    for ch in word.chars() {
+        let category = categorize_char_with(ch, &config.tokenizer);
+        match counts.iter_mut().find(|(other, _)| *other == category) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((category, 1)),
+        }
+    }
+
+    // `max_by_key` keeps the last of equal counts
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|&(_, count)| count)
+        .map(|(category, _)| category)
+}
+
+/// Length of the run of hexadecimal digits around byte offset `cursor` of `line`, or `0` when the
+/// run contains no decimal digit, as a word like `facade` is not a number.
+fn numeric_run_len(line: &str, cursor: usize) -> usize {
+    let (before, after) = line.split_at(cursor);
+    let start = before.len() - before.trim_end_matches(|ch: char| ch.is_ascii_hexdigit()).len();
+    let end = after.len() - after.trim_start_matches(|ch: char| ch.is_ascii_hexdigit()).len();
+    let run = &line[cursor - start..cursor + end];
+
+    if run.bytes().any(|byte| byte.is_ascii_digit()) {
+        run.len()
+    } else {
+        0
+    }
+}
+
+/// A unique word of the buffer, or of the other documents.
+struct Candidate<'a> {
+    word: &'a str,
+    /// Range of the first occurrence of the word in the buffer, if it occurs there.
+    first_occurrence: Option<Range>,
+    /// Distance from the cursor to the nearest occurrence in the buffer, if it occurs there.
+    proximity: Option<usize>,
+    count: usize,
+    /// Whether every occurrence is on a line containing one of `deprecatedMarkers`.
+    stale: bool,
+    /// `0` for the buffer itself, otherwise the index of the other source plus one.
+    source: usize,
+    /// Whether the word only comes from a [`Words::List`].
+    listed: bool,
+    /// Edit distance to the word before the cursor, with `exactWordCompletion`.
+    distance: usize,
+    /// Whether the word looks like a misspelling of a more frequent one, with `typoPenalty`.
+    likely_typo: bool,
+    /// Number of occurrences followed by the punctuation following the cursor, with
+    /// `punctuationContextWeight`.
+    same_punctuation: usize,
+    /// Byte offset of the subword the prefix matched, when it did not match the start.
+    subword_start: Option<usize>,
+    /// The plural of the word if it is singular and the other way round, when both occur and
+    /// `foldPlurals` made them one candidate.
+    other_form: Option<&'a str>,
+}
+
+/// Collapse the words of the buffer within the configured [`Scope`] around the cursor, followed
+/// by the words of `others`, into unique candidates, in order of first occurrence.
+///
+/// The words of the buffer and their counts are read from its index when it has all it takes,
+/// and only split again in lightweight mode or when the settings need more than the words.
+fn collect_candidates<'a>(
+    document: &'a Document,
+    &Query { prefix, position, next_punctuation, .. }: &Query,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+    others: &[Source<'a>],
+    cancelled: &AtomicBool,
+) -> Option<Vec<Candidate<'a>>> {
+    let mut candidates = Vec::<Candidate>::new();
+    let mut index = HashMap::<Cow<str>, usize>::new();
+    let key = |word: &'a str| {
+        let word = if config.fold_plurals { singular(word) } else { word };
+        match config.index_case {
+            IndexCase::Sensitive => Cow::Borrowed(word),
+            IndexCase::Folded => Cow::Owned(lowercase(word, config.case_folding)),
+        }
+    };
+    let (scope_range, first_line) = scope_range(&document.text, position.line, config.scope);
+    let scope = &document.text[scope_range.clone()];
+    let cursor = get_char_index_from_position(
+        scope,
+        Position::new(position.line.saturating_sub(first_line), position.character),
+        PositionEncoding::Utf16,
+    );
+    let buffer = Words::Text(scope);
+    let ignored = config
+        .ignored_regions
+        .get(&document.language_id)
+        .map(|rules| ignored_spans(scope, rules))
+        .unwrap_or_default();
+    let indexed = indexed_words(document, scope_range, config);
+    let sources = std::iter::once(&buffer).chain(others.iter().map(|other| &other.words));
+
+    for (source, words) in sources.enumerate() {
+        let text = match *words {
+            Words::Text(text) => text,
+            Words::List(words) => {
+                for word in words {
+                    if let Entry::Vacant(entry) = index.entry(key(word)) {
+                        entry.insert(candidates.len());
+                        candidates.push(Candidate {
+                            word,
+                            first_occurrence: None,
+                            proximity: None,
+                            count: 0,
+                            stale: false,
+                            source,
+                            listed: true,
+                            distance: 0,
+                            likely_typo: false,
+                            same_punctuation: 0,
+                            subword_start: None,
+                            other_form: None,
+                        });
+                    }
+                }
+                continue;
+            }
+        };
+        let marked_lines = marked_lines(text, &config.deprecated_markers);
+
+        if let (0, Some(words)) = (source, &indexed) {
+            let line_starts = lines_with_offsets(text).map(|(start, _)| start).collect::<Vec<_>>();
+            let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+
+            for &(word, ref offsets) in words {
+                if cancelled.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let line = line_of(offsets[0]);
+                let start = Position::new(
+                    line as u32 + first_line,
+                    character_len(&text[line_starts[line]..offsets[0]]),
+                );
+                let end = Position::new(start.line, start.character + character_len(word));
+                let same_punctuation =
+                    if config.punctuation_context_weight != 0.0 && next_punctuation.is_some() {
+                        offsets
+                            .iter()
+                            .filter(|&&offset| {
+                                text[offset + word.len()..].chars().next() == next_punctuation
+                            })
+                            .count()
+                    } else {
+                        0
+                    };
+                let proximity = offsets
+                    .iter()
+                    .map(|&offset| match config.proximity_metric {
+                        ProximityMetric::Lines => {
+                            (line_of(offset) as u32 + first_line).abs_diff(position.line) as usize
+                        }
+                        ProximityMetric::Bytes => offset.abs_diff(cursor),
+                    })
+                    .min();
+
+                add_occurrences(
+                    &mut candidates,
+                    &mut index,
+                    key(word),
+                    Candidate {
+                        word,
+                        first_occurrence: Some(Range::new(start, end)),
+                        proximity,
+                        count: offsets.len(),
+                        stale: offsets
+                            .iter()
+                            .all(|&offset| marked_lines.contains(&(line_of(offset) as u32))),
+                        source,
+                        listed: false,
+                        distance: 0,
+                        likely_typo: false,
+                        same_punctuation,
+                        subword_start: None,
+                        other_form: None,
+                    },
+                    prefix,
+                    config,
+                );
+            }
+            continue;
+        }
+
+        let mut offset = 0;
+        let mut words = split_positions(text, tokenizer, &config.tokenizer).peekable();
+        while let Some((mut start, word)) = words.next() {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+            let word_offset = offset;
+            offset += word.len();
+
+            let stale = marked_lines.contains(&start.line);
+            if source == 0 {
+                let is_ignored = ignored
+                    .get(start.line as usize)
+                    .is_some_and(|spans| spans.iter().any(|span| span.contains(&start.character)));
+                if is_ignored {
+                    continue;
+                }
+                start.line += first_line;
+            }
+            let same_punctuation = config.punctuation_context_weight != 0.0
+                && next_punctuation.is_some()
+                && words.peek().and_then(|(_, next)| next.chars().next()) == next_punctuation;
+            let proximity = (source == 0).then(|| match config.proximity_metric {
+                ProximityMetric::Lines => start.line.abs_diff(position.line) as usize,
+                ProximityMetric::Bytes => word_offset.abs_diff(cursor),
+            });
+            let end = Position::new(start.line, start.character + character_len(word));
+
+            add_occurrences(
+                &mut candidates,
+                &mut index,
+                key(word),
+                Candidate {
+                    word,
+                    first_occurrence: (source == 0).then_some(Range::new(start, end)),
+                    proximity,
+                    count: 1,
+                    stale,
+                    source,
+                    listed: false,
+                    distance: 0,
+                    likely_typo: false,
+                    same_punctuation: usize::from(same_punctuation),
+                    subword_start: None,
+                    other_form: None,
+                },
+                prefix,
+                config,
+            );
+        }
+    }
+
+    Some(candidates)
+}
+
+/// The words of `document` within the `scope` byte range, with their offsets in it, in order of
+/// first occurrence, as read from its index. `None` in lightweight mode, when a word occurs too
+/// often for the index to tell where, or when the settings need the punctuation or the position
+/// in the line of every occurrence.
+fn indexed_words<'a>(
+    document: &'a Document,
+    scope: std::ops::Range<usize>,
+    config: &Config,
+) -> Option<Vec<(&'a str, Vec<usize>)>> {
+    if config.punctuation_words || config.ignored_regions.contains_key(&document.language_id) {
+        return None;
+    }
+
+    let mut words = Vec::new();
+    for (word, occurrences) in document.occurrences.as_ref()?.offsets() {
+        let Occurrences::Tracked(offsets) = occurrences else {
+            return None;
+        };
+        let first = offsets.partition_point(|&offset| offset < scope.start);
+        let last = offsets.partition_point(|&offset| offset < scope.end);
+        if first < last {
+            let offsets = offsets[first..last].iter().map(|offset| offset - scope.start);
+            words.push((word.as_str(), offsets.collect::<Vec<_>>()));
+        }
+    }
+    words.sort_unstable_by_key(|(_, offsets)| offsets[0]);
+
+    Some(words)
+}
+
+/// Count `occurrences`, some occurrences of a word, towards the candidate of `key`, or make them
+/// a new candidate if there is none yet.
+fn add_occurrences<'a>(
+    candidates: &mut Vec<Candidate<'a>>,
+    index: &mut HashMap<Cow<'a, str>, usize>,
+    key: Cow<'a, str>,
+    occurrences: Candidate<'a>,
+    prefix: &str,
+    config: &Config,
+) {
+    match index.entry(key) {
+        Entry::Occupied(entry) => {
+            let candidate = &mut candidates[*entry.get()];
+            let word = occurrences.word;
+            candidate.count += occurrences.count;
+            candidate.same_punctuation += occurrences.same_punctuation;
+            candidate.stale &= occurrences.stale;
+            candidate.proximity = candidate
+                .proximity
+                .min(occurrences.proximity)
+                .or(occurrences.proximity);
+            if config.prefer_exact_case
+                && !prefix.is_empty()
+                && !candidate.word.starts_with(prefix)
+                && word.starts_with(prefix)
+            {
+                candidate.word = word;
+            }
+            // Spellings differing only in case have the same length, forms do not
+            if config.fold_plurals
+                && candidate.other_form.is_none()
+                && word.len() != candidate.word.len()
+            {
+                candidate.other_form = Some(word);
+            }
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(candidates.len());
+            candidates.push(occurrences);
+        }
+    }
+}
+
+/// Order the candidates, best first.
+///
+/// Candidates are scored by their number of occurrences plus `recencyWeight` times how recently
+/// an edit of `document` touched them: `1` for the last edit, `1/2` for the one before, and so on,
+/// plus `punctuationContextWeight` times their occurrences followed by the same punctuation as the
+/// cursor.
+fn rank(candidates: &mut Vec<(Candidate, bool)>, config: &Config, document: &Document) {
+    let score = |candidate: &Candidate| {
+        let recency = match document.touched.get(candidate.word) {
+            Some(&edit) => 1.0 / (1 + document.edits - edit) as f64,
+            None => 0.0,
+        };
+        candidate.count as f64
+            + config.recency_weight * recency
+            + config.punctuation_context_weight * candidate.same_punctuation as f64
+    };
+
+    // Words only found in other sources are the farthest
+    let proximity = |candidate: &Candidate| match config.ranking {
+        Ranking::Proximity => candidate.proximity.unwrap_or(usize::MAX),
+        Ranking::Frequency | Ranking::InterleaveSources => 0,
+    };
+
+    // Stable, so equally scored words stay in order of first occurrence
+    candidates.sort_by(|(a, _), (b, _)| {
+        (a.listed, a.distance, a.likely_typo, proximity(a))
+            .cmp(&(b.listed, b.distance, b.likely_typo, proximity(b)))
+            .then_with(|| score(b).total_cmp(&score(a)))
+    });
+
+    if config.ranking == Ranking::InterleaveSources {
+        let mut by_source = Vec::<VecDeque<_>>::new();
+        for candidate in candidates.drain(..) {
+            let source = candidate.0.source;
+            if by_source.len() <= source {
+                by_source.resize_with(source + 1, VecDeque::new);
+            }
+            by_source[source].push_back(candidate);
+        }
+
+        while by_source.iter().any(|candidates| !candidates.is_empty()) {
+            candidates.extend(by_source.iter_mut().filter_map(VecDeque::pop_front));
+        }
+    }
+}
+
+/// Move the best ranked spelling of each word starting with `prefix` in its exact case just
+/// ahead of the best ranked spelling of the word ignoring case, if they differ.
+fn prefer_exact_case(candidates: &mut Vec<(Candidate, bool)>, prefix: &str, folding: CaseFolding) {
+    let mut best = HashMap::<String, usize>::new();
+    let mut moved = HashSet::new();
+    let keys = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, (candidate, _))| {
+            let word = lowercase(candidate.word, folding);
+            let first = *best.entry(word.clone()).or_insert(i);
+            if first != i
+                && candidate.word.starts_with(prefix)
+                && !candidates[first].0.word.starts_with(prefix)
+                && moved.insert(word)
+            {
+                (first, 0)
+            } else {
+                (i, 1)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut keyed = candidates.drain(..).zip(keys).collect::<Vec<_>>();
+    keyed.sort_by_key(|&(_, key)| key);
+    candidates.extend(keyed.into_iter().map(|(candidate, _)| candidate));
+}
+
+/// Mark the words one edit away from `prefix` that are also one edit away from a word occurring
+/// at least [`TYPO_FREQUENCY_RATIO`] times as often, as they are likely misspellings of it.
+fn mark_likely_typos(candidates: &mut [(Candidate, bool)], prefix: &str) {
+    let likely_typos = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, (candidate, _))| {
+            !candidate.listed && edit_distance(candidate.word, prefix, 1).is_some()
+        })
+        .filter(|(i, (candidate, _))| {
+            candidates.iter().enumerate().any(|(j, (other, _))| {
+                j != *i
+                    && !other.listed
+                    && other.count >= TYPO_FREQUENCY_RATIO * candidate.count
+                    && edit_distance(candidate.word, other.word, 1).is_some()
+            })
+        })
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    for i in likely_typos {
+        candidates[i].0.likely_typo = true;
+    }
+}
+
+/// The closer of the last opener of `text` that is not closed yet, among the `opener: closer`
+/// keyword `pairs`. Openers and closers nest, and a closer closes the innermost opener it
+/// matches, so `if a; then b; fi` leaves no opener unclosed.
+fn find_unclosed<'a>(
+    text: &str,
+    pairs: &'a HashMap<String, String>,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+) -> Option<&'a str> {
+    let mut open = Vec::<&str>::new();
+
+    for (_, word) in tokenizer.words(text, &config.tokenizer) {
+        if let Some(closer) = pairs.get(word) {
+            open.push(closer);
+        } else if let Some(i) = open.iter().rposition(|&closer| closer == word) {
+            open.truncate(i);
+        }
+    }
+
+    open.pop()
+}
+
+/// The singular of `word` if it looks like a regular English plural, as in `cats` or `boxes`,
+/// otherwise `word` itself. A heuristic: irregular plurals are left alone, and some singulars
+/// ending in `s`, such as `does`, are taken for plurals.
+fn singular(word: &str) -> &str {
+    let ends_with = |s: &str, suffix: &str| {
+        s.len() >= suffix.len()
+            && s.as_bytes()[s.len() - suffix.len()..].eq_ignore_ascii_case(suffix.as_bytes())
+    };
+    if !ends_with(word, "s") {
+        return word;
+    }
+    let stem = &word[..word.len() - 1];
+    // `class`, `status` and `analysis` are not plurals, and short words like `its` rarely are
+    if stem.len() < 3 || ["s", "u", "i"].iter().any(|end| ends_with(stem, end)) {
+        return word;
+    }
+
+    // `boxes`, `classes` and `churches` add `es`, but `tables` only adds `s`
+    if ["se", "xe", "ze", "che", "she"]
+        .iter()
+        .any(|end| ends_with(stem, end))
+    {
+        &stem[..stem.len() - 1]
+    } else {
+        stem
+    }
+}
+
+/// The text following the first of `markers` in `line`, without leading whitespace, if any
+/// marker occurs in it.
+fn annotation<'a>(line: &'a str, markers: &[String]) -> Option<&'a str> {
+    let end = markers
+        .iter()
+        .filter(|marker| !marker.is_empty())
+        .filter_map(|marker| line.find(marker.as_str()).map(|start| start + marker.len()))
+        .min()?;
+
+    Some(line[end..].trim_start())
+}
+
+/// The annotations of `text` following one of `markers`, except on line `skip`, trimmed and in
+/// order of first occurrence.
+fn find_annotations<'a>(text: &'a str, markers: &[String], skip: u32) -> Vec<&'a str> {
+    let mut seen = HashSet::new();
+
+    // Unlike `str::lines`, counts lone `\r` as a line terminator like clients do
+    lines_with_offsets(text)
+        .enumerate()
+        .filter(|&(line, _)| line != skip as usize)
+        .filter_map(|(_, (_, line))| annotation(line, markers))
+        .map(str::trim_end)
+        .filter(|annotation| !annotation.is_empty() && seen.insert(*annotation))
+        .collect()
+}
+
+/// Acronyms of `text` followed by their expansion in parentheses, like
+/// `LSP (Language Server Protocol)`, in order of first occurrence.
+///
+/// The initials of the words of the expansion, or of its capitalized words only, must spell
+/// the acronym, so `FAQ (frequently asked questions)` and `USA (United States of America)` both
+/// count, but `TODO (remove this)` does not.
+fn find_acronyms(text: &str) -> Vec<(&str, &str)> {
+    let mut acronyms = Vec::<(&str, &str)>::new();
+
+    for (_, line) in lines_with_offsets(text) {
+        for (open, _) in line.match_indices('(') {
+            let Some(len) = line[open + 1..].find(')') else {
+                continue;
+            };
+            let expansion = line[open + 1..open + 1 + len].trim();
+            let before = line[..open].trim_end();
+            let acronym_start = before
+                .char_indices()
+                .rev()
+                .take_while(|(_, ch)| ch.is_uppercase() || ch.is_numeric())
+                .last()
+                .map_or(before.len(), |(i, _)| i);
+            let acronym = &before[acronym_start..];
+            let is_word_start = !before[..acronym_start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric);
+
+            if acronym.chars().filter(|ch| ch.is_uppercase()).count() < 2 || !is_word_start {
+                continue;
+            }
+
+            let words = expansion.split_whitespace().collect::<Vec<_>>();
+            let initials = |capitalized_only: bool| {
+                words
+                    .iter()
+                    .filter_map(|word| word.chars().next())
+                    .filter(|ch| !capitalized_only || ch.is_uppercase())
+                    .flat_map(char::to_uppercase)
+                    .collect::<String>()
+            };
+
+            let spells_acronym = initials(false) == acronym || initials(true) == acronym;
+            if words.len() > 1
+                && spells_acronym
+                && acronyms.iter().all(|&(other, _)| other != acronym)
+            {
+                acronyms.push((acronym, expansion));
+            }
+        }
+    }
+
+    acronyms
+}
+
+/// Numbers of the lines of `text` containing any of `markers`.
+fn marked_lines(text: &str, markers: &[String]) -> HashSet<u32> {
+    if markers.is_empty() {
+        return HashSet::new();
+    }
+
+    lines_with_offsets(text)
+        .enumerate()
+        .filter(|(_, (_, line))| markers.iter().any(|marker| line.contains(marker.as_str())))
+        .map(|(i, _)| i as u32)
+        .collect()
+}
+
+/// Column spans, per line of `text`, of the comments and strings described by `rules`.
+///
+/// Only looks at one line at a time: a quote left open at the end of the line, like the
+/// apostrophe in `don't`, ignores nothing.
+fn ignored_spans(text: &str, rules: &RegionRules) -> Vec<Vec<std::ops::Range<u32>>> {
+    lines_with_offsets(text)
+        .map(|(_, line)| {
+            let mut spans = Vec::new();
+            let mut open_quote = None;
+            let mut escaped = false;
+
+            if rules.markdown_syntax && line.trim_start().starts_with("```") {
+                spans.push(0..character_len(line));
+                return spans;
+            }
+
+            let mut skip_until = 0;
+            for (i, ch) in line.char_indices() {
+                if i < skip_until {
+                    continue;
+                }
+                match open_quote {
+                    Some((quote, start)) => {
+                        if ch == quote && !escaped {
+                            spans.push(start..character_len(&line[..i]) + 1);
+                            open_quote = None;
+                        }
+                        escaped = ch == '\\' && !escaped;
+                    }
+                    None => {
+                        let rest = &line[i..];
+                        if rules
+                            .line_comments
+                            .iter()
+                            .any(|comment| rest.starts_with(comment.as_str()))
+                        {
+                            let start = character_len(&line[..i]);
+                            spans.push(start..start + character_len(rest));
+                            break;
+                        }
+                        if rules.quotes.contains(&ch) {
+                            open_quote = Some((ch, character_len(&line[..i])));
+                        }
+                        if rules.markdown_syntax {
+                            if let Some(end) = markdown_syntax_len(rest) {
+                                let start = character_len(&line[..i]);
+                                spans.push(start..start + character_len(&rest[..end]));
+                                skip_until = i + end;
+                            }
+                        }
+                    }
+                }
+            }
+
+            spans
+        })
+        .collect()
+}
+
+/// Byte length of the Markdown syntax `rest` starts with, if any: a link target, an autolink
+/// or a marker character.
+fn markdown_syntax_len(rest: &str) -> Option<usize> {
+    let delimited =
+        |open: &str, close: char| Some(rest.strip_prefix(open)?.find(close)? + open.len() + 1);
+
+    if let Some(end) = delimited("](", ')') {
+        return Some(end);
+    }
+    if let Some(end) = delimited("<", '>').filter(|&end| {
+        let target = &rest[1..end - 1];
+        !target.contains(char::is_whitespace) && (target.contains("://") || target.contains('@'))
+    }) {
+        return Some(end);
+    }
+
+    rest.starts_with(['*', '`', '#', '>', '~', '[', ']', '!', '|'])
+        .then_some(1)
+}
+
+/// The byte range of `text` candidates are taken from, along with the line it starts at.
+fn scope_range(text: &str, line: u32, scope: Scope) -> (std::ops::Range<usize>, u32) {
+    if scope == Scope::Buffer {
+        return (0..text.len(), 0);
+    }
+
+    let is_blank = |(_, line): &(usize, &str)| line.trim().is_empty();
+    let lines = lines_with_offsets(text).collect::<Vec<_>>();
+    let line = line as usize;
+
+    match lines.get(line) {
+        Some(current) if scope == Scope::Paragraph && is_blank(current) => {
+            return (0..0, line as u32)
+        }
+        Some(_) => {}
+        None => return (0..0, line as u32),
+    }
+
+    let (first, last) = match scope {
+        Scope::Paragraph => (
+            lines[..line].iter().rposition(is_blank).map_or(0, |i| i + 1),
+            lines[line..]
+                .iter()
+                .position(is_blank)
+                .map_or(lines.len(), |i| line + i)
+                - 1,
+        ),
+        _ => (line, line),
+    };
+    let (last_start, last_line) = lines[last];
+
+    (lines[first].0..last_start + last_line.len(), first as u32)
+}
+
+/// How close `a` and `b`, `distance` edits apart, are: from `0` when every character differs to
+/// `1` when they are equal.
+fn similarity(a: &str, b: &str, distance: usize) -> f64 {
+    let len = a.chars().count().max(b.chars().count());
+    if len == 0 {
+        return 1.0;
+    }
+
+    1.0 - distance as f64 / len as f64
+}
+
+/// Levenshtein distance between `a` and `b`, in characters, or `None` if it exceeds `max`.
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        // Every later row is at least as far as the closest cell of this one
+        if current.iter().min().is_some_and(|&closest| closest > max) {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
+
+/// Where a candidate matched the typed prefix.
+pub(crate) enum Match {
+    /// At the start of the candidate, or no matching was done.
+    Word,
+    /// At the start of a subword inside the candidate, at this byte offset.
+    Subword(usize),
+}
+
+/// Match `word` against `prefix`, ignoring case under the given folding if any. Subwords are
+/// always matched ignoring case.
+pub(crate) fn match_candidate(
+    word: &str,
+    prefix: &str,
+    matching: Matching,
+    ignore_case: Option<CaseFolding>,
+) -> Option<Match> {
+    if matching == Matching::None
+        || prefix.is_empty()
+        || word.starts_with(prefix)
+        || ignore_case.is_some_and(|folding| starts_with_ignore_case(word, prefix, folding))
+    {
+        return Some(Match::Word);
+    }
+
+    let folding = ignore_case.unwrap_or_default();
+    let subword = (matching == Matching::SubwordPrefix)
+        .then(|| {
+            subword_starts(word)
+                .find(|&start| starts_with_ignore_case(&word[start..], prefix, folding))
+        })
+        .flatten();
+    if let Some(start) = subword {
+        return Some(Match::Subword(start));
+    }
+
+    None
+}
+
+/// Byte offsets at which a subword starts inside `word`, excluding the start of the word itself.
+///
+/// Subwords are separated by `_` and `-`, lowercase-to-uppercase transitions (`fooBar`), the last
+/// capital of an uppercase run (`HTTPServer`), and transitions into digits (`utf8`).
+fn subword_starts(word: &str) -> impl Iterator<Item = usize> + '_ {
+    let chars = word.char_indices().collect::<Vec<_>>();
+
+    (1..chars.len()).filter_map(move |i| {
+        let (start, ch) = chars[i];
+        let prev = chars[i - 1].1;
+        let next = chars.get(i + 1).map(|&(_, next)| next);
+
+        let is_start = matches!(prev, '_' | '-') && !matches!(ch, '_' | '-')
+            || prev.is_lowercase() && ch.is_uppercase()
+            || prev.is_uppercase() && ch.is_uppercase() && next.is_some_and(char::is_lowercase)
+            || !prev.is_numeric() && ch.is_numeric();
+
+        is_start.then_some(start)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn item<'a>(items: &'a [CompletionItem], label: &str) -> &'a CompletionItem {
+        items.iter().find(|item| item.label == label).unwrap()
+    }
+
+    #[test]
+    fn duplicates_keep_their_first_occurrence() {
+        let items = complete("bar foo\nfoo baz foo\n", Position::new(2, 0), &Config::default());
+
+        let first = Range::new(Position::new(0, 4), Position::new(0, 7));
+        assert_eq!(item(&items, "foo").data, serde_json::to_value(first).ok());
+    }
+
+    #[test]
+    fn prefix_after_an_astral_character_starts_after_it() {
+        let items = complete("foo\n😀fo", Position::new(1, 4), &Config::default());
+
+        let prefix = Range::new(Position::new(1, 2), Position::new(1, 4));
+        assert_eq!(
+            item(&items, "foo").text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit::new(prefix, "foo".to_string())))
+        );
+    }
+
+    #[test]
+    fn empty_line_offers_every_word_at_the_cursor() {
+        let items = complete("foo\n\nbar", Position::new(1, 0), &Config::default());
+
+        let cursor = Range::new(Position::new(1, 0), Position::new(1, 0));
+        assert_eq!(
+            items.iter().map(|item| item.label.as_str()).collect::<Vec<_>>(),
+            ["foo", "bar"]
+        );
+        assert_eq!(
+            item(&items, "bar").text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit::new(cursor, "bar".to_string())))
+        );
+    }
+
+    #[test]
+    fn shared_properties_become_item_defaults() {
+        let support = ItemDefaultsSupport::new(&["commitCharacters".to_string()]);
+        let list = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "src/", "commitCharacters": ["/"] },
+                { "label": "tests/", "commitCharacters": ["/"] },
+            ],
+        });
+
+        assert_eq!(
+            with_item_defaults(list.clone(), support),
+            json!({
+                "isIncomplete": false,
+                "itemDefaults": { "commitCharacters": ["/"] },
+                "items": [{ "label": "src/" }, { "label": "tests/" }],
+            })
+        );
+        // Unsupported by the client
+        assert_eq!(
+            with_item_defaults(list, ItemDefaultsSupport::default())["items"][0]
+                ["commitCharacters"],
+            json!(["/"])
+        );
+    }
+
+    #[test]
+    fn properties_some_items_lack_are_not_defaults() {
+        let support = ItemDefaultsSupport::new(&["commitCharacters".to_string()]);
+        let list = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "src/", "commitCharacters": ["/"] },
+                { "label": "README.md" },
+            ],
+        });
+
+        assert_eq!(with_item_defaults(list.clone(), support), list);
+        // Nor is anything in a bare array of items
+        let items = list["items"].clone();
+        assert_eq!(with_item_defaults(items.clone(), support), items);
+    }
+
+    #[test]
+    fn a_shared_edit_range_leaves_items_their_text() {
+        let support = ItemDefaultsSupport::new(&["editRange".to_string()]);
+        let range =
+            json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 2 } });
+        let list = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "foo", "textEdit": { "range": range, "newText": "foo" } },
+                {
+                    "label": "FOO",
+                    "filterText": "fo",
+                    "textEdit": { "range": range, "newText": "foo()" },
+                },
+            ],
+        });
+
+        assert_eq!(
+            with_item_defaults(list, support),
+            json!({
+                "isIncomplete": false,
+                "itemDefaults": { "editRange": range },
+                "items": [
+                    { "label": "foo" },
+                    { "label": "FOO", "filterText": "fo", "textEditText": "foo()" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn shared_insert_and_replace_ranges_are_one_default() {
+        let support = ItemDefaultsSupport::new(&["editRange".to_string()]);
+        let insert =
+            json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 2 } });
+        let replace =
+            json!({ "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 4 } });
+        let edit = |new_text| json!({ "insert": insert, "replace": replace, "newText": new_text });
+        let list = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "foo", "textEdit": edit("foo") },
+                { "label": "fob", "textEdit": edit("fob") },
+            ],
+        });
+
+        let list = with_item_defaults(list, support);
+        assert_eq!(
+            list["itemDefaults"],
+            json!({ "editRange": { "insert": insert, "replace": replace } })
+        );
+        assert_eq!(list["items"], json!([{ "label": "foo" }, { "label": "fob" }]));
+
+        // Items editing another range keep their own
+        let mut other = edit("foo");
+        other["replace"] = insert.clone();
+        let list = json!({
+            "isIncomplete": false,
+            "items": [
+                { "label": "foo", "textEdit": other },
+                { "label": "fob", "textEdit": edit("fob") },
+            ],
+        });
+        assert_eq!(with_item_defaults(list.clone(), support), list);
+    }
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|item| item.label.as_str()).collect()
+    }
+
+    #[test]
+    fn right_to_left_words_complete_in_logical_order() {
+        let config = Config { matching: Matching::Prefix, ..Config::default() };
+        let items = complete("שלום שלוש עולם\nשל", Position::new(1, 2), &config);
+
+        let prefix = Range::new(Position::new(1, 0), Position::new(1, 2));
+        assert_eq!(labels(&items), ["שלום", "שלוש"]);
+        assert_eq!(
+            item(&items, "שלום").text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit::new(prefix, "שלום".to_string())))
+        );
+    }
+
+    #[test]
+    fn arabic_marks_and_tatweel_stay_in_their_word() {
+        // The fatha U+064E and the tatweel U+0640 are parts of words, not separators
+        let config = Config { matching: Matching::Prefix, ..Config::default() };
+        let text = "كَتَبَ كـتاب كتب\nكَت\nكـت";
+
+        let items = complete(text, Position::new(1, 3), &config);
+        let prefix = Range::new(Position::new(1, 0), Position::new(1, 3));
+        assert_eq!(labels(&items), ["كَتَبَ"]);
+        assert_eq!(
+            item(&items, "كَتَبَ").text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit::new(prefix, "كَتَبَ".to_string())))
+        );
+
+        let items = complete(text, Position::new(2, 3), &config);
+        assert_eq!(labels(&items), ["كـتاب"]);
+    }
+
+    #[test]
+    fn hebrew_points_stay_in_their_word() {
+        let config = Config { matching: Matching::Prefix, ..Config::default() };
+        let items = complete("שָׁלוֹם שלום\nשָׁ", Position::new(1, 3), &config);
+
+        assert_eq!(labels(&items), ["שָׁלוֹם"]);
+    }
+
+    #[test]
+    fn right_to_left_words_do_not_merge_with_latin_ones() {
+        let config = Config { matching: Matching::Prefix, ..Config::default() };
+
+        let items = complete("wordשלום\nשל", Position::new(1, 2), &config);
+        assert_eq!(labels(&items), ["שלום"]);
+        let items = complete("wordשלום\nwo", Position::new(1, 2), &config);
+        assert_eq!(labels(&items), ["word"]);
+    }
+
+    #[test]
+    fn cache_keeps_the_entries_an_edit_cannot_change() {
+        let key = |prefix: &str| CacheKey {
+            position: Position::new(0, prefix.len() as u32),
+            line: prefix.to_string(),
+            prefix: prefix.to_string(),
+        };
+        let cached = |cache: &CompletionCache| {
+            ["fo", "ba", "fo."].map(|prefix| cache.get(&key(prefix)).is_some())
+        };
+        let fill = || {
+            let mut cache = CompletionCache::default();
+            for prefix in ["fo", "ba", "fo."] {
+                cache.insert(key(prefix), CompletionList::default());
+            }
+            cache
+        };
+        let config = Config { matching: Matching::Prefix, ..Config::default() };
+        let edited = HashSet::from(["food".to_string(), "qux".to_string()]);
+
+        let mut cache = fill();
+        cache.invalidate(&edited, &config, "plaintext");
+        assert_eq!(cached(&cache), [false, true, false]);
+
+        let mut cache = fill();
+        cache.invalidate(&HashSet::new(), &config, "plaintext");
+        assert_eq!(cached(&cache), [true, true, false]);
+
+        // Every word may be offered for any prefix without matching
+        let mut cache = fill();
+        cache.invalidate(&edited, &Config::default(), "plaintext");
+        assert_eq!(cached(&cache), [false, false, false]);
+    }
+
+    #[test]
+    fn scope_restricts_the_words_offered() {
+        let text = "one two\n\nthree four\nfive \n\nsix";
+        let offered = |scope| {
+            let config = Config { scope, ..Config::default() };
+            let items = complete(text, Position::new(3, 5), &config);
+            let mut labels = items.into_iter().map(|item| item.label).collect::<Vec<_>>();
+            labels.sort();
+            labels
+        };
+
+        assert_eq!(offered(Scope::Buffer), ["five", "four", "one", "six", "three", "two"]);
+        assert_eq!(offered(Scope::Paragraph), ["five", "four", "three"]);
+        assert_eq!(offered(Scope::Line), ["five"]);
+    }
+
+    #[test]
+    fn interleaved_sources_take_turns() {
+        let text = "aa aa aa ab ab ab ac ac ac\na";
+        let document = Document::new(
+            text.into(),
+            0,
+            "plaintext".into(),
+            true,
+            &CategoryTokenizer,
+            &Config::default(),
+        );
+        let others = [Source { name: "other", words: Words::Text("ad ae") }];
+        let offered = |ranking| {
+            let config = Config {
+                matching: Matching::Prefix,
+                ranking,
+                ..Config::default()
+            };
+            let list = complete_document(
+                &document,
+                Position::new(1, 1),
+                &CategoryTokenizer,
+                &config,
+                CompletionSupport::default(),
+                &others,
+                &AtomicBool::new(false),
+            )
+            .unwrap();
+            list.items.into_iter().map(|item| item.label).collect::<Vec<_>>()
+        };
+
+        assert_eq!(offered(Ranking::Frequency), ["aa", "ab", "ac", "ad", "ae"]);
+        assert_eq!(offered(Ranking::InterleaveSources), ["aa", "ad", "ab", "ae", "ac"]);
+    }
+
+    #[test]
+    fn exact_word_completion_offers_the_closest_words_first() {
+        let text = "hold help hello hello world\nhelo";
+        let config = Config { exact_word_completion: true, ..Config::default() };
+        let items = complete(text, Position::new(1, 4), &config);
+
+        // By distance, then most frequent first
+        assert_eq!(labels(&items), ["hello", "help", "hold"]);
+        let config = Config { max_edit_distance: 1, ..config };
+        let items = complete(text, Position::new(1, 4), &config);
+        assert_eq!(labels(&items), ["hello", "help"]);
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+    }
+
+    #[test]
+    fn fold_plurals_offers_one_form_per_word() {
+        let config = Config {
+            fold_plurals: true,
+            matching: Matching::Prefix,
+            ..Config::default()
+        };
+
+        let items = complete("cats cat boxes box\nca", Position::new(1, 2), &config);
+        assert_eq!(labels(&items), ["cats"]);
+        let items = complete("cats cat boxes box\nbox", Position::new(1, 3), &config);
+        assert_eq!(labels(&items), ["boxes"]);
+        let items = complete("cats cat class classes\ncl", Position::new(1, 2), &config);
+        assert_eq!(labels(&items), ["class"]);
+
+        for (word, singular_form) in [
+            ("cats", "cat"),
+            ("boxes", "box"),
+            ("tables", "table"),
+            ("status", "status"),
+            ("its", "its"),
+        ] {
+            assert_eq!(singular(word), singular_form);
+        }
+    }
+
+    #[test]
+    fn indexed_and_split_words_complete_alike() {
+        let text =
+            "Cats cat, cat; dog.\r\n\nold cat // TODO-deprecated\n漢字 😀 cats\rdogs cat.\nc";
+        let configs = [
+            Config::default(),
+            Config { ranking: Ranking::Proximity, ..Config::default() },
+            Config {
+                ranking: Ranking::Proximity,
+                proximity_metric: ProximityMetric::Bytes,
+                ..Config::default()
+            },
+            Config { scope: Scope::Paragraph, ..Config::default() },
+            Config {
+                deprecated_markers: vec!["TODO-deprecated".into()],
+                ..Config::default()
+            },
+            Config {
+                punctuation_context_weight: 1.0,
+                ..Config::default()
+            },
+            Config {
+                fold_plurals: true,
+                index_case: IndexCase::Folded,
+                ..Config::default()
+            },
+        ];
+
+        for config in configs {
+            let lightweight = Config { lightweight: true, ..config.clone() };
+            for position in [Position::new(5, 1), Position::new(2, 3)] {
+                let indexed = complete(text, position, &config);
+                let split = complete(text, position, &lightweight);
+                assert_eq!(indexed, split, "{config:?} at {position:?}");
+            }
+        }
+    }
+}
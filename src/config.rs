@@ -0,0 +1,578 @@
+use std::{borrow::Cow, collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::text::CharCategory;
+
+/// Server settings, read from `initializationOptions` and `workspace/didChangeConfiguration`.
+///
+/// Every field is optional on the wire; missing fields keep their default value.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    /// On hover, look for a `word: value` or `word = value` line in the buffer and show its value
+    /// instead of the occurrence count.
+    pub hover_definitions: bool,
+    /// Show swatches for hex color tokens like `#ff8800`. Read at startup only.
+    pub document_colors: bool,
+    /// Seconds without any request or notification after which caches of closed buffers are
+    /// released. Disabled when unset.
+    pub idle_timeout: Option<u64>,
+    /// How candidates are matched against the word before the cursor.
+    pub matching: Matching,
+    /// What accepting a completion in the middle of a word does, for clients that cannot let the
+    /// user choose themselves.
+    pub insert_mode: InsertMode,
+    /// Which part of the buffer completion candidates are taken from.
+    pub scope: Scope,
+    /// Words that only occur on lines containing one of these markers, such as `TODO remove` or
+    /// `~~`, are tagged as deprecated so clients de-emphasize them. Disabled when empty.
+    pub deprecated_markers: Vec<String>,
+    /// Also offer the words of the other open documents.
+    pub complete_from_all_documents: bool,
+    /// Also offer the words of the files under the workspace folders, which are read in the
+    /// background at startup. Hidden files and directories are skipped, and at most 1000 files
+    /// of up to 1 MiB are read. Read at startup only.
+    pub complete_from_workspace: bool,
+    /// How many workspace files are read at the same time for [`Self::complete_from_workspace`].
+    /// Defaults to the number of available cores.
+    pub indexing_concurrency: Option<usize>,
+    /// Also offer runs of two or more punctuation characters, such as `:-)` or `==>`, and complete
+    /// them when one is being typed. Punctuation is never offered otherwise.
+    pub punctuation_words: bool,
+    /// How the length of words is measured by length limits.
+    pub length_unit: LengthUnit,
+    /// When the text before the cursor looks like a path, such as `docs/in` after a space, quote or
+    /// parenthesis, complete it with the files and directories under the workspace root.
+    pub path_completion: bool,
+    /// With [`Self::path_completion`], typing `/` accepts the selected directory, which is then
+    /// inserted without its trailing `/`. Only for clients supporting per-item commit characters.
+    pub path_commit_characters: bool,
+    /// Instead of completing the word before the cursor, offer the words within
+    /// [`Self::max_edit_distance`] of it, closest first, to correct typos.
+    pub exact_word_completion: bool,
+    /// Largest Levenshtein distance of the corrections offered by
+    /// [`Self::exact_word_completion`].
+    pub max_edit_distance: usize,
+    /// Corrections offered by [`Self::exact_word_completion`] must be at least this similar to
+    /// the typed word, from `0` for no character in common to `1` for the word itself: one minus
+    /// the edit distance over the length of the longer word. Disabled when `0`.
+    pub min_fuzzy_score: f64,
+    /// Rank words one edit away from the typed word after the others when they are also one edit
+    /// away from a word occurring several times as often, as they are likely misspellings of it.
+    pub typo_penalty: bool,
+    /// Whether words differing only in case are one completion candidate.
+    pub index_case: IndexCase,
+    /// Rank the spelling of a word starting with the typed prefix in its exact case above the
+    /// spellings of the same word in other cases, as in `Http` above `HTTP` and `http` after
+    /// typing `Htt`. With [`IndexCase::Folded`], offer the word in that spelling if it occurs.
+    pub prefer_exact_case: bool,
+    /// Make the singular and plural of an English word, as in `cat` and `cats` or `box` and
+    /// `boxes`, one completion candidate, offered in the form matching what was typed. Only
+    /// regular plurals ending in `s` are recognized, and approximately.
+    pub fold_plurals: bool,
+    /// Which rules ignoring case follows, when matching and with [`IndexCase::Folded`].
+    pub case_folding: CaseFolding,
+    /// How completed paths are sorted.
+    pub collation: Collation,
+    /// When the buffer spells out an acronym as in `LSP (Language Server Protocol)`, typing the
+    /// acronym also offers its expansion. A heuristic, off by default.
+    pub acronym_expansions: bool,
+    /// When the cursor follows one of [`Self::annotation_markers`] on its line, also offer the
+    /// text following the markers on the other lines, so annotations are phrased alike.
+    pub annotation_completion: bool,
+    /// Markers starting an annotation for [`Self::annotation_completion`].
+    pub annotation_markers: Vec<String>,
+    /// Keep an index of the trigrams of the words of every document, so
+    /// [`Self::exact_word_completion`] only computes the edit distance of words sharing enough
+    /// trigrams with the typed word. Faster on large documents, at the cost of memory.
+    pub trigram_index: bool,
+    /// Words occurring more than this many times in a document, as in generated files, only have
+    /// their occurrences counted rather than recorded, to bound the memory of the index. Rename
+    /// and completion then split the text again to find them. Unlimited when unset.
+    pub max_occurrences_tracked: Option<usize>,
+    /// Keep no per-document index and find completions by splitting the buffer on each request,
+    /// to save memory. Hover then only shows definitions, and rename, [`Self::trigram_index`] and
+    /// [`Self::recency_weight`] have no effect.
+    pub lightweight: bool,
+    /// File the indices of the open documents are saved to on shutdown, and read from on
+    /// startup so reopening unchanged documents does not index them again. Read at startup only.
+    pub index_snapshot_path: Option<PathBuf>,
+    /// How completion candidates are ordered.
+    pub ranking: Ranking,
+    /// How the distance to the cursor is measured with [`Ranking::Proximity`].
+    pub proximity_metric: ProximityMetric,
+    /// How much words touched by recent edits are boosted, in occurrences: the word touched by
+    /// the last edit ranks as if it occurred this many more times. Disabled when `0`.
+    pub recency_weight: f64,
+    /// How much words are boosted, in occurrences, for each of their occurrences followed by the
+    /// same punctuation as the cursor, such as words ending sentences when a period follows it.
+    /// An experimental heuristic, disabled when `0`.
+    pub punctuation_context_weight: f64,
+    /// File with additional words to complete, one per line. They are ranked below the words
+    /// found in documents.
+    pub wordlist_path: Option<PathBuf>,
+    /// Only offer words occurring at least this many times, leaving out one-off words and typos.
+    pub min_occurrences: usize,
+    /// Do not offer words that already appear on the line of the cursor.
+    pub exclude_current_line: bool,
+    /// Do not offer the words of the selection, for clients reporting it with the
+    /// `buffer-ls.setSelection` command.
+    pub exclude_selection: bool,
+    /// Always offer the word being typed itself, last, so accepting it keeps the word as typed
+    /// and dismisses the completion popup.
+    pub include_prefix: bool,
+    /// Characters that accept the selected completion and are then typed, such as `.` or `(`.
+    /// Read at startup only.
+    pub commit_characters: Vec<String>,
+    /// Maximum number of completion items returned per request. Unlimited when unset.
+    pub max_completion_items: Option<usize>,
+    /// Also offer the words seen in every document opened or closed during the session, below
+    /// the words of the documents themselves, the most frequent and recently seen first.
+    pub global_vocabulary: bool,
+    /// With [`Self::global_vocabulary`], the weight of a word is halved every this many changes
+    /// of any document it goes without being seen. Never decays when `0`.
+    pub vocabulary_half_life: u64,
+    /// Number of words deleted from the documents that are remembered and offered, after every
+    /// other completion, to put them back. Disabled when `0`.
+    pub kill_ring_size: usize,
+    /// No completions are offered while the cursor is in a run of at least this many hexadecimal
+    /// digits, including a decimal one, as found in hex dumps and numeric tables. Disabled when
+    /// unset.
+    pub max_numeric_run: Option<usize>,
+    /// Treat documents as a single line, taking line terminators for spaces, for clients
+    /// sending positions as offsets from the start of the document on line `0`, as some do for
+    /// single-line JSON or CSV with line breaks in quotes. Read when a document is opened.
+    pub single_line: bool,
+    /// Lines longer than this many columns get a hint diagnostic. Disabled when unset.
+    pub max_line_length: Option<usize>,
+    /// Milliseconds without changes to a document after which its diagnostics are updated, so
+    /// they do not flicker while typing. Updated on every change when `0`.
+    pub diagnostics_debounce: u64,
+    /// Maximum number of completion items per category of their first character, such as
+    /// `word`, `punctuation` or `kanji`, so one kind of token does not crowd out the others.
+    /// Categories not listed are unlimited.
+    pub max_items_per_category: HashMap<CharCategory, usize>,
+    /// Minimum length of the words offered as completions, in [`Self::length_unit`]s, per
+    /// category of most of their characters, so one-character kanji words can be offered while
+    /// one-letter Latin ones are not. Categories not listed have no minimum.
+    pub min_length_per_category: HashMap<CharCategory, usize>,
+    /// URI schemes of the documents whose words are offered as completions. Documents with
+    /// other schemes, such as diff views or other virtual buffers, are tracked but not indexed.
+    pub schemes: Vec<String>,
+    /// Per language identifier, keywords opening a block with the keyword closing it, such as
+    /// `begin: end` or `if: fi`. The closer of the innermost block still open before the cursor
+    /// is offered first. A heuristic counting keywords, not a parser.
+    pub closing_keywords: HashMap<String, HashMap<String, String>>,
+    /// Per language identifier, comments and strings whose words are not offered as completions.
+    /// This is a line-based heuristic, not a parser.
+    pub ignored_regions: HashMap<String, RegionRules>,
+    /// Per workspace folder name, settings overriding these ones for the documents of the folder,
+    /// such as prose settings for a `docs` folder next to code.
+    pub folder_settings: HashMap<String, Value>,
+    #[serde(flatten)]
+    pub tokenizer: TokenizerConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            hover_definitions: false,
+            document_colors: false,
+            idle_timeout: None,
+            matching: Matching::default(),
+            insert_mode: InsertMode::default(),
+            scope: Scope::default(),
+            deprecated_markers: Vec::new(),
+            complete_from_all_documents: false,
+            complete_from_workspace: false,
+            indexing_concurrency: None,
+            punctuation_words: false,
+            length_unit: LengthUnit::default(),
+            path_completion: false,
+            path_commit_characters: false,
+            exact_word_completion: false,
+            max_edit_distance: 2,
+            min_fuzzy_score: 0.0,
+            typo_penalty: false,
+            index_case: IndexCase::default(),
+            prefer_exact_case: false,
+            fold_plurals: false,
+            case_folding: CaseFolding::default(),
+            collation: Collation::default(),
+            acronym_expansions: false,
+            annotation_completion: false,
+            annotation_markers: ["TODO:", "FIXME:", "NOTE:"].map(String::from).to_vec(),
+            trigram_index: false,
+            max_occurrences_tracked: Some(10_000),
+            lightweight: false,
+            index_snapshot_path: None,
+            ranking: Ranking::default(),
+            proximity_metric: ProximityMetric::default(),
+            recency_weight: 0.0,
+            punctuation_context_weight: 0.0,
+            wordlist_path: None,
+            min_occurrences: 1,
+            exclude_current_line: false,
+            exclude_selection: false,
+            include_prefix: false,
+            commit_characters: Vec::new(),
+            max_completion_items: None,
+            global_vocabulary: false,
+            vocabulary_half_life: 1000,
+            kill_ring_size: 0,
+            max_numeric_run: None,
+            single_line: false,
+            max_line_length: None,
+            diagnostics_debounce: 500,
+            max_items_per_category: HashMap::new(),
+            min_length_per_category: HashMap::new(),
+            schemes: vec!["file".to_string(), "untitled".to_string()],
+            closing_keywords: HashMap::new(),
+            ignored_regions: HashMap::new(),
+            folder_settings: HashMap::new(),
+            tokenizer: TokenizerConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The settings of the documents of the workspace folder named `folder`: these ones, with
+    /// the [`Self::folder_settings`] of the folder on top. Invalid folder settings are ignored,
+    /// they are reported when the settings are read.
+    pub fn for_folder(&self, folder: Option<&str>) -> Cow<'_, Self> {
+        match folder.and_then(|folder| self.folder_settings.get(folder)) {
+            Some(settings) => {
+                let mut config = self.clone();
+                config.merge(settings.clone());
+                Cow::Owned(config)
+            }
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    /// Whether the indices built with `self` must be rebuilt to follow `other`.
+    pub fn index_differs(&self, other: &Self) -> bool {
+        self.tokenizer != other.tokenizer
+            || self.trigram_index != other.trigram_index
+            || self.max_occurrences_tracked != other.max_occurrences_tracked
+            || self.lightweight != other.lightweight
+    }
+
+    /// Update the fields present in `settings`, keeping the current value of the others.
+    ///
+    /// Fields with an invalid value also keep their current value, so one bad setting does not
+    /// discard the rest. Objects are checked field by field, so a bad field inside one, such as
+    /// a language of [`Self::ignored_regions`], does not discard its valid fields either. Returns
+    /// a message for each invalid or unknown field, naming it by its dotted path.
+    pub fn merge(&mut self, settings: Value) -> Vec<String> {
+        let settings = match settings {
+            Value::Object(settings) => settings,
+            Value::Null => return Vec::new(),
+            other => return vec![format!("expected an object, got {other}")],
+        };
+        let mut merged = serde_json::to_value(&*self).expect("configuration serializes");
+        let mut errors = Vec::new();
+
+        for (key, value) in settings {
+            let Some(previous) = merged.get(&key).cloned() else {
+                errors.push(format!("{key}: unknown setting"));
+                continue;
+            };
+            merge_field(&mut merged, &[key], value, Some(previous), &mut errors);
+        }
+
+        if let Ok(config) = serde_json::from_value(merged) {
+            *self = config;
+        }
+
+        errors
+    }
+}
+
+/// Set the field at `path` of the serialized configuration `merged` to `value`, or back to
+/// `previous` if the configuration no longer deserializes, retrying the fields of an object one
+/// by one first.
+fn merge_field(
+    merged: &mut Value,
+    path: &[String],
+    value: Value,
+    previous: Option<Value>,
+    errors: &mut Vec<String>,
+) {
+    let is_valid = |merged: &Value| serde_json::from_value::<Config>(merged.clone());
+
+    set_field(merged, path, Some(value.clone()));
+    let Err(err) = is_valid(merged) else {
+        return;
+    };
+
+    if let Value::Object(fields) = value {
+        set_field(merged, path, Some(Value::Object(Map::new())));
+        if is_valid(merged).is_ok() {
+            let previous = match previous {
+                Some(Value::Object(previous)) => previous,
+                _ => Map::new(),
+            };
+            for (field, value) in fields {
+                let path = [path, std::slice::from_ref(&field)].concat();
+                merge_field(merged, &path, value, previous.get(&field).cloned(), errors);
+            }
+            return;
+        }
+    }
+
+    errors.push(format!("{}: {err}", path.join(".")));
+    set_field(merged, path, previous);
+}
+
+/// Set the field at `path` of `value` to `field`, or remove it when `None`. The objects along
+/// the path must exist.
+fn set_field(value: &mut Value, path: &[String], field: Option<Value>) {
+    let (last, parents) = path.split_last().expect("a field path is not empty");
+    let parent = parents.iter().fold(value, |value, key| &mut value[key.as_str()]);
+    let Value::Object(parent) = parent else {
+        unreachable!("the parents of a field are objects");
+    };
+
+    match field {
+        Some(field) => parent.insert(last.clone(), field),
+        None => parent.remove(last),
+    };
+}
+
+/// How completion candidates are matched against the typed prefix.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Matching {
+    /// Return every word and leave the filtering to the client.
+    #[default]
+    None,
+    /// Only return words starting with the prefix.
+    Prefix,
+    /// Like [`Matching::Prefix`], but the prefix may also match, ignoring case, at the start of
+    /// any subword: `bar` matches `fooBar` and `foo_bar`.
+    SubwordPrefix,
+}
+
+/// What accepting a completion in the middle of a word does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InsertMode {
+    /// Replace the part of the word before the cursor, keeping the rest.
+    Insert,
+    /// Replace the whole word.
+    #[default]
+    Replace,
+}
+
+/// How words are keyed when collecting completion candidates.
+///
+/// Folding trades an allocation per word for merging `Foo` and `foo`, and lets the typed prefix
+/// match regardless of case without a second pass over the candidates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IndexCase {
+    /// Words differing in case are distinct candidates, matched case-sensitively.
+    #[default]
+    Sensitive,
+    /// Words are keyed by their lowercase form and offered as their first occurrence is spelled,
+    /// with the occurrences of every spelling counted together. The prefix matches ignoring case.
+    Folded,
+}
+
+/// Unit the length of words is measured in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LengthUnit {
+    /// Unicode scalar values.
+    #[default]
+    Chars,
+    /// User-perceived characters, so `é` spelled with a combining accent or the `👩‍💻` emoji
+    /// sequence count as one: Unicode extended grapheme clusters.
+    Graphemes,
+}
+
+/// How characters are lowercased when case is ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CaseFolding {
+    /// The default Unicode lowercase mapping.
+    #[default]
+    Unicode,
+    /// Like [`CaseFolding::Unicode`], but `I` lowercases to dotless `ı` and `İ` to `i`, as in
+    /// Turkish and Azerbaijani.
+    Turkish,
+}
+
+/// How words and names are sorted alphabetically.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Collation {
+    /// By code point, so `Z` sorts before `a` and `é` after `z`.
+    #[default]
+    Codepoint,
+    /// Ignoring accents and case first, so `école` sorts between `eau` and `fable`, then by code
+    /// point. Close to the order of most languages written in Latin, Greek or Cyrillic scripts,
+    /// without their specific rules.
+    Unicode,
+}
+
+/// How completion candidates are ordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Ranking {
+    /// Most frequent words first.
+    #[default]
+    Frequency,
+    /// Take the most frequent words of each document in turn, so a single large document does
+    /// not crowd out the others when completing from all documents.
+    InterleaveSources,
+    /// Words occurring nearest to the cursor first, then the most frequent ones.
+    Proximity,
+}
+
+/// How the distance between an occurrence and the cursor is measured.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProximityMetric {
+    /// Number of lines in between, so every word of a nearby line is near.
+    #[default]
+    Lines,
+    /// Number of bytes in between.
+    Bytes,
+}
+
+/// Part of the buffer completion candidates are taken from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Scope {
+    /// The whole buffer.
+    #[default]
+    Buffer,
+    /// The paragraph around the cursor, delimited by blank lines.
+    Paragraph,
+    /// The line of the cursor.
+    Line,
+}
+
+/// Comments and strings of a language, recognized line by line.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RegionRules {
+    /// Markers starting a comment that runs to the end of the line, such as `//` or `#`.
+    pub line_comments: Vec<String>,
+    /// Characters delimiting strings, such as `"` or `'`. A backslash escapes the next character.
+    pub quotes: Vec<char>,
+    /// Also ignore Markdown syntax, so only prose and link text are offered: emphasis, heading,
+    /// quote and code markers, link targets as in `[text](url)`, autolinks as in
+    /// `<https://example.com>` and code fence lines.
+    pub markdown_syntax: bool,
+}
+
+/// Settings that change how buffer text is split into words.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TokenizerConfig {
+    /// Keep a leading sigil (one of [`Self::sigils`]) as part of the word that immediately
+    /// follows it, so hashtags and mentions like `#project` or `@alice` complete as a whole.
+    pub sigil_tokens: bool,
+    /// Characters treated as sigils when [`Self::sigil_tokens`] is enabled.
+    pub sigils: String,
+    /// Overrides for characters whose role as a word separator is ambiguous, such as the Ogham
+    /// space mark (`\u{1680}`, not a separator by default) or the zero width space (`\u{200B}`, a
+    /// separator by default).
+    ///
+    /// `true` makes the character a separator, like whitespace. `false` makes it part of the
+    /// token it appears in, so it never introduces a word boundary.
+    pub separators: HashMap<char, bool>,
+    /// Keep zero width joiners and non-joiners and variation selectors in the token they appear
+    /// in, so emoji sequences like `👩‍💻` and words of Indic or Persian scripts are not split.
+    /// [`Self::separators`] takes precedence.
+    pub joiners: bool,
+    /// Keep contractions and possessives like `don't` or `it’s` as one word: a straight or curly
+    /// apostrophe between two word characters is part of the word.
+    pub apostrophe_words: bool,
+    /// Split words at underscores, so `foo_bar` is completed as `foo` and `bar`, instead of
+    /// treating `_` as a word character.
+    pub underscore_separator: bool,
+    /// Split words where letters and digits meet, so `abc123` is completed as `abc` and `123`,
+    /// instead of treating them as one word.
+    pub split_digits: bool,
+    /// Whether runs of hiragana, katakana or kanji are words as a whole or one per character.
+    pub cjk_completion: CjkCompletion,
+}
+
+impl Default for TokenizerConfig {
+    fn default() -> Self {
+        Self {
+            sigil_tokens: false,
+            sigils: "#@".to_string(),
+            separators: HashMap::new(),
+            joiners: true,
+            apostrophe_words: false,
+            underscore_separator: false,
+            split_digits: false,
+            cjk_completion: CjkCompletion::default(),
+        }
+    }
+}
+
+/// How runs of CJK characters are split into words.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CjkCompletion {
+    /// A contiguous run of the same script is one word.
+    #[default]
+    Run,
+    /// Every character is a word of its own.
+    Character,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn merge_keeps_valid_settings_of_a_partially_invalid_payload() {
+        let mut config = Config::default();
+
+        let errors = config.merge(json!({
+            "matching": "prefixIgnoreCase",
+            "minOccurrences": 3,
+            "splitDigits": true,
+            "ignoredRegions": { "rust": { "lineComments": ["//"], "quotes": 5 } },
+            "tokenizer": { "splitDigits": true },
+        }));
+
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        // Settings are read in alphabetical order
+        assert!(errors[0].starts_with("ignoredRegions.rust.quotes: invalid type"));
+        assert!(errors[1].starts_with("matching: unknown variant `prefixIgnoreCase`"));
+        assert_eq!(errors[2], "tokenizer: unknown setting");
+        assert_eq!(config.matching, Matching::None);
+        assert_eq!(config.min_occurrences, 3);
+        assert!(config.tokenizer.split_digits);
+        let rust = &config.ignored_regions["rust"];
+        assert_eq!(rust.line_comments, ["//"]);
+        assert!(rust.quotes.is_empty());
+    }
+
+    #[test]
+    fn merge_rolls_a_bad_nested_field_back_to_its_previous_value() {
+        let mut config = Config::default();
+        config.merge(json!({ "ignoredRegions": { "rust": { "quotes": ["\""] } } }));
+
+        let errors = config.merge(json!({
+            "ignoredRegions": { "rust": { "lineComments": ["//"], "quotes": "'" } },
+        }));
+
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        let rust = &config.ignored_regions["rust"];
+        assert_eq!(rust.line_comments, ["//"]);
+        assert_eq!(rust.quotes, ['"']);
+    }
+}
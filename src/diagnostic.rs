@@ -0,0 +1,45 @@
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Position, Range};
+
+use crate::{get_byte_offset_in_line, lines_with_offsets, PositionEncoding};
+
+/// Source of the diagnostics, shown by clients next to their message.
+const DIAGNOSTIC_SOURCE: &str = "buffer-ls";
+
+/// A hint for each line of `text` longer than `max_line_length` columns, counted in `encoding`
+/// code units like the positions sent to the client so the hint starts where its ruler is.
+///
+/// The hint covers the part of the line past the limit.
+pub fn line_length_diagnostics(
+    text: &str,
+    max_line_length: usize,
+    encoding: PositionEncoding,
+) -> Vec<Diagnostic> {
+    let max = u32::try_from(max_line_length).unwrap_or(u32::MAX);
+
+    lines_with_offsets(text)
+        .enumerate()
+        .filter_map(|(line_number, (_, line))| {
+            let len: u32 = line.chars().map(|ch| encoding.len(ch)).sum();
+            if len <= max {
+                return None;
+            }
+            // A character straddling the limit is included in the hint
+            let start: u32 = line[..get_byte_offset_in_line(line, max, encoding)]
+                .chars()
+                .map(|ch| encoding.len(ch))
+                .sum();
+            let line_number = line_number as u32;
+
+            Some(Diagnostic {
+                range: Range::new(
+                    Position::new(line_number, start),
+                    Position::new(line_number, len),
+                ),
+                severity: Some(DiagnosticSeverity::HINT),
+                source: Some(DIAGNOSTIC_SOURCE.to_string()),
+                message: format!("line is {len} columns long, more than {max_line_length}"),
+                ..Diagnostic::default()
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,302 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use tower_lsp::lsp_types::TextDocumentContentChangeEvent;
+
+use crate::{
+    completion::CompletionCache,
+    config::{Config, TokenizerConfig},
+    get_char_index_from_position,
+    index::{OccurrenceIndex, Occurrences},
+    text::{categorize_char_with, CharCategory},
+    tokenizer::Tokenizer,
+    PositionEncoding,
+};
+
+/// A text document the client has opened.
+#[derive(Debug, Default)]
+pub struct Document {
+    pub text: String,
+    /// Version of the text, as sent by the client.
+    pub version: i32,
+    /// Language identifier sent by the client, such as `rust` or `markdown`.
+    pub language_id: String,
+    /// Whether the client still has the document open. Closed documents are kept as a cache
+    /// until the server goes idle.
+    pub open: bool,
+    /// Whether the words of the document are offered as completions. Documents whose URI scheme
+    /// is not listed in `schemes` are tracked, but not indexed.
+    pub indexed: bool,
+    pub completion_cache: CompletionCache,
+    /// Number of changes applied since the document was opened.
+    pub edits: u64,
+    /// Words touched by a change, with the value of [`Self::edits`] after the last change that
+    /// touched them.
+    pub touched: HashMap<String, u64>,
+    /// Words of the selection last reported with `buffer-ls.setSelection`. They are kept as words
+    /// rather than a range, as the user is about to type over the selection.
+    pub selected_words: HashSet<String>,
+    /// Occurrences of the words of the text, unless [`Config::lightweight`] is enabled.
+    pub occurrences: Option<OccurrenceIndex>,
+    /// Whether the text is kept as a single line, see [`Config::single_line`]. The text given
+    /// when opening the document must have gone through [`join_lines`] already.
+    pub single_line: bool,
+}
+
+impl Document {
+    pub fn new(
+        text: String,
+        version: i32,
+        language_id: String,
+        indexed: bool,
+        tokenizer: &dyn Tokenizer,
+        config: &Config,
+    ) -> Self {
+        let occurrences = index(&text, tokenizer, config);
+
+        Self::with_occurrences(text, version, language_id, indexed, occurrences)
+    }
+
+    /// Like [`Self::new`], with the occurrences of `text` already indexed.
+    pub fn with_occurrences(
+        text: String,
+        version: i32,
+        language_id: String,
+        indexed: bool,
+        occurrences: Option<OccurrenceIndex>,
+    ) -> Self {
+        Self {
+            occurrences,
+            text,
+            version,
+            language_id,
+            open: true,
+            indexed,
+            completion_cache: CompletionCache::default(),
+            edits: 0,
+            touched: HashMap::new(),
+            selected_words: HashSet::new(),
+            single_line: false,
+        }
+    }
+
+    /// Rebuild [`Self::occurrences`] from scratch, after the settings it depends on changed or
+    /// on request.
+    pub fn reindex(&mut self, tokenizer: &dyn Tokenizer, config: &Config) {
+        self.occurrences = index(&self.text, tokenizer, config);
+        if self.occurrences.is_none() {
+            self.touched.clear();
+        }
+    }
+
+    /// Apply the changes that bring the document to `version`.
+    ///
+    /// When `deleted` is given, the words the changes removed whole are pushed to it, in the order
+    /// they were removed.
+    ///
+    /// Returns the words of the lines the changes touched, before and after them, to invalidate
+    /// the completions depending on them. Returns `None` when a change replaced the whole text or
+    /// added or removed lines, which may have changed the position of any word.
+    pub fn apply_changes(
+        &mut self,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+        mut deleted: Option<&mut Vec<String>>,
+    ) -> Option<HashSet<String>> {
+        let mut edited = Some(HashSet::new());
+        for change in changes {
+            if let (Some(deleted), Some(range)) = (deleted.as_deref_mut(), change.range) {
+                let start =
+                    get_char_index_from_position(&self.text, range.start, PositionEncoding::Utf16);
+                let end =
+                    get_char_index_from_position(&self.text, range.end, PositionEncoding::Utf16);
+                deleted.extend(deleted_words(&self.text, start, end, tokenizer, config));
+            }
+            let words = self.apply_change(change, tokenizer, config);
+            edited = edited.zip(words).map(|(mut edited, words)| {
+                edited.extend(words);
+                edited
+            });
+        }
+
+        self.version = version;
+        edited
+    }
+
+    /// Apply `change`, returning the words of the lines it touched as for
+    /// [`Self::apply_changes`].
+    fn apply_change(
+        &mut self,
+        mut change: TextDocumentContentChangeEvent,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+    ) -> Option<Vec<String>> {
+        self.edits += 1;
+        if self.single_line {
+            change.text = join_lines(change.text);
+        }
+
+        // Nothing tells which words a full replacement touched. Indexing it from scratch beats
+        // removing then adding every word.
+        let Some(range) = change.range else {
+            self.text = change.text;
+            if let Some(occurrences) = &mut self.occurrences {
+                occurrences.rebuild(&self.text, tokenizer, config);
+            }
+            return None;
+        };
+
+        let start = get_char_index_from_position(&self.text, range.start, PositionEncoding::Utf16);
+        let end = get_char_index_from_position(&self.text, range.end, PositionEncoding::Utf16);
+
+        // Words never span lines, so only the lines of the change need to be split again
+        let (line_start, line_end) = line_bounds(&self.text, start, end);
+        let delta = change.text.len() as isize - (end - start) as isize;
+        let new_line_end = line_end.saturating_add_signed(delta);
+
+        let mut edited = (line_breaks(&self.text[start..end]) == line_breaks(&change.text))
+            .then(|| words_of(&self.text[line_start..line_end], tokenizer, config));
+
+        if let Some(occurrences) = &mut self.occurrences {
+            occurrences.remove(&self.text[line_start..line_end], line_start, tokenizer, config);
+            occurrences.shift(line_end, delta);
+        }
+        self.text.replace_range(start..end, &change.text);
+        if let Some(occurrences) = &mut self.occurrences {
+            occurrences.add(&self.text[line_start..new_line_end], line_start, tokenizer, config);
+            self.touch_words(start, start + change.text.len(), tokenizer, config);
+        }
+
+        if let Some(edited) = &mut edited {
+            edited.extend(words_of(&self.text[line_start..new_line_end], tokenizer, config));
+        }
+        edited
+    }
+
+    /// Number of words of the text, counting repeated words every time.
+    pub fn word_count(&self, tokenizer: &dyn Tokenizer, config: &TokenizerConfig) -> usize {
+        match &self.occurrences {
+            Some(occurrences) => occurrences.offsets().values().map(Occurrences::count).sum(),
+            None => tokenizer.words(&self.text, config).len(),
+        }
+    }
+
+    /// Offsets of the occurrences of `word`, in order, or `None` in lightweight mode. The text is
+    /// split again when the word occurs too often for the index to track them.
+    pub fn offsets_of(
+        &self,
+        word: &str,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+    ) -> Option<Cow<'_, [usize]>> {
+        let occurrences = self.occurrences.as_ref()?;
+
+        Some(match occurrences.get(word) {
+            Some(offsets) => Cow::Borrowed(offsets),
+            None => Cow::Owned(
+                tokenizer
+                    .words(&self.text, config)
+                    .into_iter()
+                    .filter(|&(_, other)| other == word)
+                    .map(|(start, _)| start)
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Record the words overlapping the `start..end` byte range as touched by the last change.
+    fn touch_words(
+        &mut self,
+        start: usize,
+        end: usize,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+    ) {
+        let (line_start, line_end) = line_bounds(&self.text, start, end);
+        let mut offset = line_start;
+
+        for word in tokenizer.split(&self.text[line_start..line_end], config) {
+            let word_end = offset + word.len();
+            let is_word = word.chars().next().map(|ch| categorize_char_with(ch, config))
+                == Some(CharCategory::Word);
+
+            if is_word && word_end >= start && offset <= end {
+                self.touched.insert(word.to_string(), self.edits);
+            }
+            offset = word_end;
+        }
+    }
+}
+
+/// Replace the line terminators of `text` with spaces, which are as long in bytes and in UTF-16
+/// code units, so `text` becomes a single line with every offset unchanged.
+pub fn join_lines(text: String) -> String {
+    if !text.contains(['\r', '\n']) {
+        return text;
+    }
+
+    text.replace(['\r', '\n'], " ")
+}
+
+/// The index of `text`, or `None` in lightweight mode.
+fn index(text: &str, tokenizer: &dyn Tokenizer, config: &Config) -> Option<OccurrenceIndex> {
+    (!config.lightweight).then(|| {
+        OccurrenceIndex::new(
+            text,
+            tokenizer,
+            &config.tokenizer,
+            config.trigram_index,
+            config.max_occurrences_tracked,
+        )
+    })
+}
+
+/// The words of `text` lying entirely within the `start..end` byte range. Words only partly in
+/// the range are being edited rather than deleted.
+fn deleted_words(
+    text: &str,
+    start: usize,
+    end: usize,
+    tokenizer: &dyn Tokenizer,
+    config: &TokenizerConfig,
+) -> Vec<String> {
+    if start >= end {
+        return Vec::new();
+    }
+    let (line_start, line_end) = line_bounds(text, start, end);
+
+    tokenizer
+        .words(&text[line_start..line_end], config)
+        .into_iter()
+        .filter(|&(offset, word)| {
+            line_start + offset >= start && line_start + offset + word.len() <= end
+        })
+        .map(|(_, word)| word.to_string())
+        .collect()
+}
+
+/// The words of `text`.
+fn words_of(text: &str, tokenizer: &dyn Tokenizer, config: &TokenizerConfig) -> Vec<String> {
+    tokenizer
+        .words(text, config)
+        .into_iter()
+        .map(|(_, word)| word.to_string())
+        .collect()
+}
+
+/// Number of line terminators in `text`, counting `\r\n` as two.
+fn line_breaks(text: &str) -> usize {
+    text.matches(['\r', '\n']).count()
+}
+
+/// Byte range of the lines of `text` overlapping `start..end`, without the final terminator.
+fn line_bounds(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let line_start = text[..start].rfind(['\r', '\n']).map_or(0, |i| i + 1);
+    let line_end = text[end..].find(['\r', '\n']).map_or(text.len(), |i| end + i);
+
+    (line_start, line_end)
+}
@@ -0,0 +1,65 @@
+use tower_lsp::lsp_types::{Hover, HoverContents, MarkupContent, MarkupKind, Position, Range};
+
+use crate::{
+    config::Config, document::Document, find_word_at_position, get_position_from_char_index,
+    lines_with_offsets, tokenizer::Tokenizer, PositionEncoding,
+};
+
+/// Compute the hover for the word at `position` in `document`.
+///
+/// When [`Config::hover_definitions`] is enabled and the buffer contains a `word: value` or
+/// `word = value` line, the value is shown. Otherwise the hover shows how many times the word
+/// occurs in the buffer, unless [`Config::lightweight`] is enabled.
+pub fn hover(
+    document: &Document,
+    position: Position,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+) -> Option<Hover> {
+    let text = &document.text;
+    let (start, word) = find_word_at_position(text, position, tokenizer, &config.tokenizer)?;
+
+    let definition = config
+        .hover_definitions
+        .then(|| find_definition(text, word))
+        .flatten();
+    let value = match (definition, &document.occurrences) {
+        (Some(definition), _) => definition,
+        (None, Some(occurrences)) => match occurrences.count(word) {
+            1 => "1 occurrence".to_string(),
+            count => format!("{count} occurrences"),
+        },
+        (None, None) => return None,
+    };
+
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::PlainText, value }),
+        range: Some(Range::new(
+            get_position_from_char_index(text, start, PositionEncoding::Utf16),
+            get_position_from_char_index(text, start + word.len(), PositionEncoding::Utf16),
+        )),
+    })
+}
+
+/// Find the value of the first line that looks like `word: value` or `word = value`.
+fn find_definition(text: &str, word: &str) -> Option<String> {
+    lines_with_offsets(text).find_map(|(_, line)| {
+        let rest = line.trim_start().strip_prefix(word)?.trim_start();
+        let value = rest.strip_prefix([':', '='])?.trim();
+
+        (!value.is_empty()).then(|| value.to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions_are_found_whatever_the_line_endings() {
+        for text in ["a: 1\nb: 2\n", "a: 1\r\nb: 2\r\n", "a: 1\rb: 2\r"] {
+            assert_eq!(find_definition(text, "a").as_deref(), Some("1"), "{text:?}");
+            assert_eq!(find_definition(text, "b").as_deref(), Some("2"), "{text:?}");
+        }
+    }
+}
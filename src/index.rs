@@ -0,0 +1,457 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::TokenizerConfig, tokenizer::Tokenizer};
+
+/// Byte offsets of the occurrences of every word of a text, kept up to date through edits so
+/// features working on the word under the cursor do not have to split the whole text again.
+#[derive(Debug, Default)]
+pub struct OccurrenceIndex {
+    /// Occurrences, per word.
+    offsets: HashMap<String, Occurrences>,
+    /// Words containing each trigram, when enabled.
+    trigrams: Option<HashMap<[char; 3], HashSet<String>>>,
+    /// Words occurring more often than this only have their occurrences counted. Unlimited when
+    /// unset.
+    max_tracked: Option<usize>,
+}
+
+/// The occurrences of a word in an [`OccurrenceIndex`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Occurrences {
+    /// Sorted byte offsets.
+    Tracked(Vec<usize>),
+    /// How many there are, for words occurring too often to record where. A word stays
+    /// untracked until it disappears or the text is indexed again, as the index cannot tell
+    /// where its occurrences are.
+    Untracked(usize),
+}
+
+impl Occurrences {
+    pub fn count(&self) -> usize {
+        match self {
+            Self::Tracked(offsets) => offsets.len(),
+            Self::Untracked(count) => *count,
+        }
+    }
+
+    /// Stop tracking the occurrences if there are more than `max_tracked`.
+    fn limit(&mut self, max_tracked: Option<usize>) {
+        if let Self::Tracked(offsets) = self {
+            if max_tracked.is_some_and(|max| offsets.len() > max) {
+                *self = Self::Untracked(offsets.len());
+            }
+        }
+    }
+}
+
+impl OccurrenceIndex {
+    /// Index the whole of `text` in a single pass, also indexing trigrams if enabled.
+    ///
+    /// Words come in order, so their offsets are appended without searching where they go, as
+    /// [`Self::add`] has to.
+    pub fn new(
+        text: &str,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+        trigrams: bool,
+        max_tracked: Option<usize>,
+    ) -> Self {
+        let mut offsets = HashMap::<String, Vec<usize>>::new();
+        for (start, word) in tokenizer.words(text, config) {
+            match offsets.get_mut(word) {
+                Some(offsets) => offsets.push(start),
+                None => {
+                    offsets.insert(word.to_string(), vec![start]);
+                }
+            }
+        }
+        let offsets = offsets
+            .into_iter()
+            .map(|(word, offsets)| (word, Occurrences::Tracked(offsets)))
+            .collect();
+
+        Self::from_offsets(offsets, trigrams, max_tracked)
+    }
+
+    /// An index of the given occurrences, also indexing trigrams if enabled and no longer
+    /// tracking the occurrences of words occurring more than `max_tracked` times.
+    pub fn from_offsets(
+        mut offsets: HashMap<String, Occurrences>,
+        trigrams: bool,
+        max_tracked: Option<usize>,
+    ) -> Self {
+        for occurrences in offsets.values_mut() {
+            occurrences.limit(max_tracked);
+        }
+
+        let trigrams = trigrams.then(|| {
+            let mut index = HashMap::<[char; 3], HashSet<String>>::new();
+            for word in offsets.keys() {
+                for trigram in self::trigrams(word) {
+                    index.entry(trigram).or_default().insert(word.clone());
+                }
+            }
+            index
+        });
+
+        Self { offsets, trigrams, max_tracked }
+    }
+
+    /// Index `text` from scratch, keeping the trigram and tracking settings.
+    pub fn rebuild(&mut self, text: &str, tokenizer: &dyn Tokenizer, config: &TokenizerConfig) {
+        *self = Self::new(text, tokenizer, config, self.trigrams.is_some(), self.max_tracked);
+    }
+
+    /// The words that may be within `max_distance` edits of `query`, or `None` if the trigram
+    /// index is disabled or cannot rule out any word.
+    ///
+    /// An edit changes at most three trigrams, so such a word shares all but `3 * max_distance`
+    /// of the trigrams of `query`.
+    pub fn near(&self, query: &str, max_distance: usize) -> Option<HashSet<&str>> {
+        let index = self.trigrams.as_ref()?;
+        let query = trigrams(query).collect::<HashSet<_>>();
+        let required = query.len().checked_sub(3 * max_distance).filter(|&n| n > 0)?;
+        let mut shared = HashMap::<&str, usize>::new();
+
+        for words in query.iter().filter_map(|trigram| index.get(trigram)) {
+            for word in words {
+                *shared.entry(word).or_default() += 1;
+            }
+        }
+
+        Some(
+            shared
+                .into_iter()
+                .filter(|&(_, n)| n >= required)
+                .map(|(word, _)| word)
+                .collect(),
+        )
+    }
+
+    /// Occurrences of every word.
+    pub fn offsets(&self) -> &HashMap<String, Occurrences> {
+        &self.offsets
+    }
+
+    /// Offsets of the occurrences of `word`, in order, or `None` if it occurs too often for them
+    /// to be tracked.
+    pub fn get(&self, word: &str) -> Option<&[usize]> {
+        match self.offsets.get(word) {
+            Some(Occurrences::Tracked(offsets)) => Some(offsets),
+            Some(Occurrences::Untracked(_)) => None,
+            None => Some(&[]),
+        }
+    }
+
+    /// Number of occurrences of `word`, tracked or not.
+    pub fn count(&self, word: &str) -> usize {
+        self.offsets.get(word).map_or(0, Occurrences::count)
+    }
+
+    /// Record the words of `text`, a part of the indexed text starting at `offset` that holds no
+    /// indexed word yet.
+    ///
+    /// The new occurrences of a word all go to the same place among its existing ones, so they
+    /// are inserted at once, keeping large pastes linear in the size of the text.
+    pub fn add(
+        &mut self,
+        text: &str,
+        offset: usize,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+    ) {
+        for (word, starts) in group_by_word(tokenizer.words(text, config)) {
+            let occurrences = match self.offsets.entry(word.to_string()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    if let Some(index) = &mut self.trigrams {
+                        for trigram in trigrams(word) {
+                            index.entry(trigram).or_default().insert(word.to_string());
+                        }
+                    }
+                    entry.insert(Occurrences::Tracked(Vec::new()))
+                }
+            };
+            match occurrences {
+                Occurrences::Tracked(offsets) => {
+                    let at = offsets.partition_point(|&other| other < offset);
+                    offsets.splice(at..at, starts.into_iter().map(|start| offset + start));
+                }
+                Occurrences::Untracked(count) => *count += starts.len(),
+            }
+            occurrences.limit(self.max_tracked);
+        }
+    }
+
+    /// Forget the words of `text`, a part of the indexed text starting at `offset`.
+    pub fn remove(
+        &mut self,
+        text: &str,
+        offset: usize,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+    ) {
+        for (word, starts) in group_by_word(tokenizer.words(text, config)) {
+            let Some(occurrences) = self.offsets.get_mut(word) else {
+                continue;
+            };
+            match occurrences {
+                Occurrences::Tracked(offsets) => {
+                    // Every occurrence within `text` is one of its words
+                    let first = offsets.partition_point(|&other| other < offset);
+                    let last = offsets.partition_point(|&other| other < offset + text.len());
+                    offsets.drain(first..last);
+                }
+                Occurrences::Untracked(count) => *count = count.saturating_sub(starts.len()),
+            }
+            if occurrences.count() == 0 {
+                self.offsets.remove(word);
+                if let Some(index) = &mut self.trigrams {
+                    for trigram in trigrams(word) {
+                        if let Entry::Occupied(mut entry) = index.entry(trigram) {
+                            entry.get_mut().remove(word);
+                            if entry.get().is_empty() {
+                                entry.remove();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move the occurrences at or after `from` by `delta` bytes, after an edit before them
+    /// changed the length of the text.
+    ///
+    /// An edit only shortens the text before `from` by what it removed, so the occurrences never
+    /// move before the start of the text. Should a broken edit try to, they stop at the start.
+    pub fn shift(&mut self, from: usize, delta: isize) {
+        for occurrences in self.offsets.values_mut() {
+            let Occurrences::Tracked(offsets) = occurrences else {
+                continue;
+            };
+            let first = offsets.partition_point(|&offset| offset < from);
+            for offset in &mut offsets[first..] {
+                *offset = offset.saturating_add_signed(delta);
+            }
+        }
+    }
+}
+
+/// The trigrams of `word`, padded with a marker on both sides so a word has one trigram per
+/// character.
+fn trigrams(word: &str) -> impl Iterator<Item = [char; 3]> {
+    let chars = ['\0']
+        .into_iter()
+        .chain(word.chars())
+        .chain(['\0'])
+        .collect::<Vec<_>>();
+
+    chars
+        .windows(3)
+        .map(|window| [window[0], window[1], window[2]])
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+/// The offsets of each word of `words`, in order.
+fn group_by_word(words: Vec<(usize, &str)>) -> HashMap<&str, Vec<usize>> {
+    let mut grouped = HashMap::<&str, Vec<usize>>::new();
+    for (start, word) in words {
+        grouped.entry(word).or_default().push(start);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use tower_lsp::lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+    use super::*;
+    use crate::{config::Config, document::Document, tokenizer::CategoryTokenizer};
+
+    /// The start and end `(line, character)` of a change, and the text replacing them.
+    type Change<'a> = ((u32, u32), (u32, u32), &'a str);
+
+    /// Apply each `(start, end, text)` change to a document of `text` in turn, checking after
+    /// every one that the incrementally updated index matches the index of the new text.
+    fn assert_edits_keep_index(config: &Config, text: &str, changes: &[Change]) {
+        let mut document = Document::new(
+            text.to_string(),
+            0,
+            "plaintext".to_string(),
+            true,
+            &CategoryTokenizer,
+            config,
+        );
+
+        for (version, &((start_line, start), (end_line, end), text)) in changes.iter().enumerate() {
+            let change = TextDocumentContentChangeEvent {
+                range: Some(Range::new(
+                    Position::new(start_line, start),
+                    Position::new(end_line, end),
+                )),
+                range_length: None,
+                text: text.to_string(),
+            };
+            document.apply_changes(
+                version as i32 + 1,
+                vec![change],
+                &CategoryTokenizer,
+                &config.tokenizer,
+                None,
+            );
+
+            let incremental = document.occurrences.as_ref().unwrap();
+            let rebuilt = OccurrenceIndex::new(
+                &document.text,
+                &CategoryTokenizer,
+                &config.tokenizer,
+                config.trigram_index,
+                config.max_occurrences_tracked,
+            );
+            let words =
+                |index: &OccurrenceIndex| index.offsets.keys().cloned().collect::<BTreeSet<_>>();
+            assert_eq!(words(incremental), words(&rebuilt), "{:?}", document.text);
+            assert_eq!(incremental.trigrams, rebuilt.trigrams, "{:?}", document.text);
+            for (word, occurrences) in &incremental.offsets {
+                // Untracked words stay so until they disappear, only their count must match
+                match occurrences {
+                    Occurrences::Tracked(_) => {
+                        assert_eq!(
+                            Some(occurrences),
+                            rebuilt.offsets.get(word),
+                            "{word} in {:?}",
+                            document.text
+                        )
+                    }
+                    Occurrences::Untracked(count) => {
+                        assert_eq!(*count, rebuilt.count(word), "{word} in {:?}", document.text)
+                    }
+                }
+            }
+        }
+    }
+
+    fn config() -> Config {
+        Config { trigram_index: true, ..Config::default() }
+    }
+
+    #[test]
+    fn edits_within_lines_keep_offsets() {
+        assert_edits_keep_index(
+            &config(),
+            "alpha beta alpha\ngamma beta\n",
+            &[
+                // Into, before and after words, then deleting a word and joining two
+                ((0, 8), (0, 8), "x"),
+                ((0, 0), (0, 0), "new "),
+                ((1, 10), (1, 10), " beta"),
+                ((0, 4), (0, 10), ""),
+                ((0, 3), (0, 5), ""),
+                // Replacing a word by several on the last line
+                ((2, 0), (2, 0), "end"),
+                ((2, 0), (2, 3), "one two one"),
+            ],
+        );
+    }
+
+    #[test]
+    fn edits_adding_or_removing_lines_keep_offsets() {
+        assert_edits_keep_index(
+            &config(),
+            "alpha beta\ngamma delta\nalpha\n",
+            &[
+                ((0, 6), (0, 6), "new lines\nand more "),
+                ((0, 2), (2, 3), ""),
+                ((1, 0), (1, 0), "x\n\ny "),
+                ((0, 0), (3, 0), "all\ngone\n"),
+            ],
+        );
+    }
+
+    #[test]
+    fn large_pastes_keep_offsets() {
+        let paste = "lorem ipsum dolor\nsit amet lorem\n".repeat(500);
+
+        assert_edits_keep_index(
+            &config(),
+            "before\nmiddle after\n",
+            &[((1, 6), (1, 6), &paste), ((0, 3), (800, 2), "")],
+        );
+    }
+
+    #[test]
+    fn edits_around_carriage_returns_keep_offsets() {
+        assert_edits_keep_index(
+            &config(),
+            "foo\r\nbar\rbaz\nqux",
+            &[
+                // A lone \r before \r\n, then removing terminators
+                ((0, 3), (0, 3), "\r"),
+                ((0, 3), (2, 0), " "),
+                // A \n right after a lone \r makes \r\n, joining the lines it separated
+                ((0, 7), (0, 7), "\r"),
+                ((1, 0), (1, 0), "\nnew"),
+                ((0, 1), (1, 2), "a\rb\r\nc"),
+            ],
+        );
+    }
+
+    #[test]
+    fn edits_next_to_sigils_keep_offsets() {
+        let mut config = config();
+        config.tokenizer.sigil_tokens = true;
+
+        assert_edits_keep_index(
+            &config,
+            "#tag @bob tag bob",
+            &[
+                ((0, 10), (0, 10), "@"),
+                ((0, 0), (0, 1), ""),
+                ((0, 4), (0, 4), "#"),
+                ((0, 5), (0, 6), ""),
+                ((0, 0), (0, 0), "@@ #"),
+            ],
+        );
+    }
+
+    #[test]
+    fn edits_past_the_tracking_cap_keep_counts() {
+        let config = Config { max_occurrences_tracked: Some(2), ..config() };
+
+        assert_edits_keep_index(
+            &config,
+            "a b a\nb",
+            &[
+                // `a` and then `b` stop being tracked
+                ((0, 5), (0, 5), " a a"),
+                ((1, 1), (1, 1), " b\nb"),
+                // Dropping below the cap keeps them untracked, until they disappear
+                ((0, 0), (0, 6), ""),
+                ((0, 0), (0, 3), "c"),
+                ((1, 0), (2, 1), "a"),
+                ((0, 0), (1, 1), ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn shifting_never_moves_an_occurrence_before_the_start() {
+        let mut index = OccurrenceIndex::new(
+            "ab cd",
+            &CategoryTokenizer,
+            &TokenizerConfig::default(),
+            false,
+            None,
+        );
+
+        index.shift(3, -10);
+        assert_eq!(index.get("cd"), Some(&[0][..]));
+    }
+}
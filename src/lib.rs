@@ -0,0 +1,2120 @@
+//! The engine of buffer-language-server, a language server completing the words found in the
+//! open buffers.
+//!
+//! [`Backend`] implements [`LanguageServer`], so it can be served over any transport, or driven
+//! directly from tests. Servers wrap it in a [`BackendService`], which completes the responses
+//! with what lsp-types cannot express.
+
+mod casing;
+mod color;
+mod command;
+pub mod completion;
+pub mod config;
+mod diagnostic;
+mod document;
+mod hover;
+mod index;
+mod path;
+mod rename;
+mod snapshot;
+mod symbol;
+pub mod text;
+pub mod tokenizer;
+mod vocabulary;
+mod workspace;
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    result::Result as StdResult,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::{
+    sync::Semaphore,
+    task::{JoinHandle, JoinSet},
+};
+use tower::Service;
+use tower_lsp::{
+    jsonrpc::{self, Result},
+    lsp_types::*,
+    Client, ExitedError, LanguageServer, LspService,
+};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    color::{color_presentations, document_colors},
+    command::{
+        tokenize, CompleteArguments, DocumentInfo, SetSelectionArguments, TokenizeArguments,
+    },
+    completion::{
+        complete_document, complete_prefix, with_item_defaults, CacheKey, CompletionSupport,
+        ItemDefaultsSupport, Source, Words,
+    },
+    config::{Config, LengthUnit, TokenizerConfig},
+    diagnostic::line_length_diagnostics,
+    document::{join_lines, Document},
+    hover::hover,
+    index::OccurrenceIndex,
+    path::{complete_path, DirectoryCache},
+    rename::rename,
+    snapshot::Snapshot,
+    symbol::workspace_symbols,
+    text::{categorize_char_with, CharCategory},
+    tokenizer::Tokenizer,
+    vocabulary::Vocabulary,
+    workspace::{folder_of, list_workspace, read_workspace_file, WorkspaceFile},
+};
+
+const FAILED_TO_ACQUIRE_LOCK_MSG: &str = "failed to acquire lock";
+
+/// Byte offset of the first character of the given line.
+///
+/// Line boundaries are found by scanning the actual terminators (`\n`, `\r\n` or a lone `\r`)
+/// rather than assuming a uniform width, so files with mixed line endings resolve correctly.
+fn get_line_start(s: &str, line: usize) -> Option<usize> {
+    if line == 0 {
+        return Some(0);
+    }
+
+    let bytes = s.as_bytes();
+    let mut current_line = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' => i += 1,
+            b'\r' if bytes.get(i + 1) == Some(&b'\n') => i += 2,
+            b'\r' => i += 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+
+        current_line += 1;
+        if current_line == line {
+            return Some(i);
+        }
+    }
+
+    None
+}
+
+/// Content of the given line without its terminator, or `None` if the line is out of range.
+fn get_line(s: &str, line: usize) -> Option<&str> {
+    let rest = &s[get_line_start(s, line)?..];
+    let end = rest.find(['\r', '\n']).unwrap_or(rest.len());
+
+    Some(&rest[..end])
+}
+
+/// Iterate over the lines of `s` along with the byte offset each starts at, splitting on the same
+/// terminators as [`get_line_start`]. Terminators are not included in the yielded lines.
+fn lines_with_offsets(s: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = Some(0);
+
+    std::iter::from_fn(move || {
+        let line_start = start?;
+        let rest = &s[line_start..];
+        let end = rest.find(['\r', '\n']);
+
+        start = end.map(|end| {
+            let terminator = if rest[end..].starts_with("\r\n") { 2 } else { 1 };
+            line_start + end + terminator
+        });
+
+        Some((line_start, &rest[..end.unwrap_or(rest.len())]))
+    })
+}
+
+/// `position`, moved to the end of the last line of `s` if its line is past the end, to the end
+/// of its line if its character is, and back to the start of the character it is in the middle of.
+fn clamp_position(s: &str, position: Position) -> Position {
+    match lines_with_offsets(s)
+        .take(position.line as usize + 1)
+        .enumerate()
+        .last()
+    {
+        Some((index, (_, line))) if index as u32 == position.line => {
+            let mut character = 0;
+            for ch in line.chars() {
+                let next = character + ch.len_utf16() as u32;
+                if next > position.character {
+                    break;
+                }
+                character = next;
+            }
+            Position::new(position.line, character)
+        }
+        Some((last, (_, line))) => Position::new(last as u32, character_len(line)),
+        None => position,
+    }
+}
+
+/// Length of `s` in the unit of [`Position::character`]: UTF-16 code units.
+///
+/// Characters outside the Basic Multilingual Plane, such as emoji or CJK extension B
+/// ideographs, count as two.
+fn character_len(s: &str) -> u32 {
+    s.chars().map(|ch| ch.len_utf16() as u32).sum()
+}
+
+/// Length of the word `s` in `unit`s, as compared against length limits.
+fn word_len(s: &str, unit: LengthUnit) -> usize {
+    match unit {
+        LengthUnit::Chars => s.chars().count(),
+        LengthUnit::Graphemes => s.graphemes(true).count(),
+    }
+}
+
+/// Unit [`Position::character`] is counted in.
+///
+/// The server only speaks the UTF-16 default of the protocol, but the offset helpers take the
+/// encoding at every call site, so hosts receiving UTF-8 offsets from elsewhere can use them too.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// Bytes.
+    Utf8,
+    #[default]
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// Number of code units of `ch` in this encoding.
+    fn len(self, ch: char) -> u32 {
+        match self {
+            Self::Utf8 => ch.len_utf8() as u32,
+            Self::Utf16 => ch.len_utf16() as u32,
+        }
+    }
+}
+
+/// Byte offset in `line` of the given [`Position::character`], clamped to the end of the line.
+/// An offset in the middle of a multi-unit character resolves to the start of the character.
+fn get_byte_offset_in_line(line: &str, character: u32, encoding: PositionEncoding) -> usize {
+    let mut units = 0;
+
+    line.char_indices()
+        .find(|&(_, ch)| {
+            units += encoding.len(ch);
+            units > character
+        })
+        .map_or(line.len(), |(i, _)| i)
+}
+
+/// Byte offset in `s` of `position`, counted in `encoding`. Positions past the end of their line
+/// resolve to its end, and positions past the last line to the end of `s`.
+pub fn get_char_index_from_position(
+    s: &str,
+    position: Position,
+    encoding: PositionEncoding,
+) -> usize {
+    let Some(line_start) = get_line_start(s, position.line as usize) else {
+        return s.len();
+    };
+    let line = get_line(s, position.line as usize).unwrap_or_default();
+
+    line_start + get_byte_offset_in_line(line, position.character, encoding)
+}
+
+/// Like [`get_position_from_char_index`] for sorted offsets, in a single pass over `s`.
+fn get_positions_from_char_indices(
+    s: &str,
+    indices: &[usize],
+    encoding: PositionEncoding,
+) -> Vec<Position> {
+    let bytes = s.as_bytes();
+    let mut line = 0;
+    let mut line_start = 0;
+    let mut i = 0;
+
+    indices
+        .iter()
+        .map(|&index| {
+            let index = index.min(s.len());
+
+            while i < index {
+                match bytes[i] {
+                    b'\n' => i += 1,
+                    b'\r' if bytes.get(i + 1) == Some(&b'\n') => i += 2,
+                    b'\r' => i += 1,
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                }
+
+                line += 1;
+                line_start = i;
+            }
+
+            let character = s
+                .get(line_start..index)
+                .map_or(0, |before| before.chars().map(|ch| encoding.len(ch)).sum());
+
+            Position::new(line as u32, character)
+        })
+        .collect()
+}
+
+/// Inverse of [`get_char_index_from_position`]: the position of the given byte offset.
+pub fn get_position_from_char_index(s: &str, index: usize, encoding: PositionEncoding) -> Position {
+    get_positions_from_char_indices(s, &[index], encoding)[0]
+}
+
+/// Sets the flag it holds when dropped.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// How often the idle watcher checks the configuration while the idle timeout is disabled.
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The settings documents get, depending on their workspace folder.
+#[derive(Debug, Default)]
+struct EffectiveConfigs {
+    /// Settings of the documents outside of the folders with `folderSettings`.
+    base: Arc<Config>,
+    /// Settings of the documents of each folder with `folderSettings`, by folder name.
+    folders: HashMap<String, Arc<Config>>,
+}
+
+/// The language server, holding the open documents and the settings.
+///
+/// Clones share their state, so one can be moved into background tasks.
+#[derive(Debug, Clone)]
+pub struct Backend {
+    client: Client,
+    tokenizer: Arc<dyn Tokenizer>,
+    documents: Arc<Mutex<HashMap<Url, Document>>>,
+    config: Arc<Mutex<Config>>,
+    completion_support: Arc<Mutex<CompletionSupport>>,
+    last_activity: Arc<Mutex<Instant>>,
+    /// Words loaded from `wordlistPath`.
+    wordlist: Arc<Mutex<Vec<String>>>,
+    /// Words recently deleted from any document, most recent first, when `killRingSize` is set.
+    kill_ring: Arc<Mutex<Vec<String>>>,
+    /// Words seen in every document, when `globalVocabulary` is enabled.
+    vocabulary: Arc<Mutex<Vocabulary>>,
+    /// Workspace folders sent in `initialize`, as changed since. Documents get the
+    /// `folderSettings` of the folder containing them, and paths are completed from it.
+    folders: Arc<Mutex<Vec<WorkspaceFolder>>>,
+    /// Files read from the workspace folders, when `completeFromWorkspace` is enabled.
+    workspace_files: Arc<Mutex<Vec<WorkspaceFile>>>,
+    directory_cache: Arc<Mutex<DirectoryCache>>,
+    /// Indices read from `indexSnapshotPath`, taken as documents are opened.
+    snapshot: Arc<Mutex<Option<Snapshot>>>,
+    /// Tasks running in the background, aborted when the server stops.
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// Diagnostics waiting for `diagnosticsDebounce` to pass without another change, per
+    /// document.
+    diagnostic_timers: Arc<Mutex<HashMap<Url, JoinHandle<()>>>>,
+    /// Documents whose last published diagnostics were not empty, and must be cleared.
+    diagnosed: Arc<Mutex<HashSet<Url>>>,
+    /// The settings of the documents, kept up to date with [`Self::config`] and
+    /// [`Self::folders`].
+    effective_configs: Arc<Mutex<EffectiveConfigs>>,
+    /// Whether [`Self::stop`] already ran.
+    stopped: Arc<AtomicBool>,
+}
+
+impl Backend {
+    /// Create a backend splitting words with the given tokenizer, such as
+    /// [`CategoryTokenizer`](tokenizer::CategoryTokenizer).
+    pub fn new(client: Client, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        Self {
+            client,
+            tokenizer,
+            documents: Arc::new(Mutex::new(HashMap::new())),
+            config: Arc::new(Mutex::new(Config::default())),
+            completion_support: Arc::new(Mutex::new(CompletionSupport::default())),
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+            wordlist: Arc::new(Mutex::new(Vec::new())),
+            kill_ring: Arc::new(Mutex::new(Vec::new())),
+            vocabulary: Arc::new(Mutex::new(Vocabulary::default())),
+            folders: Arc::new(Mutex::new(Vec::new())),
+            workspace_files: Arc::new(Mutex::new(Vec::new())),
+            directory_cache: Arc::new(Mutex::new(DirectoryCache::default())),
+            snapshot: Arc::new(Mutex::new(None)),
+            tasks: Arc::new(Mutex::new(Vec::new())),
+            diagnostic_timers: Arc::new(Mutex::new(HashMap::new())),
+            diagnosed: Arc::new(Mutex::new(HashSet::new())),
+            effective_configs: Arc::new(Mutex::new(EffectiveConfigs::default())),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Clamp `position` to the text of `uri`, logging positions that were not valid in it. The
+    /// client may briefly be ahead of the changes the server has seen during rapid edits.
+    async fn clamped_position(&self, uri: &Url, position: Position) -> Result<Position> {
+        let Some((clamped, version)) = lock(&self.documents)?
+            .get(uri)
+            .map(|document| (clamp_position(&document.text, position), document.version))
+        else {
+            return Ok(position);
+        };
+
+        if clamped != position {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!(
+                        "{}:{} is not a valid position in version {version} of {uri}, likely \
+                         computed against a newer version, using {}:{} instead",
+                        position.line, position.character, clamped.line, clamped.character
+                    ),
+                )
+                .await;
+        }
+
+        Ok(clamped)
+    }
+
+    /// The settings of `uri`, with the `folderSettings` of its workspace folder applied.
+    fn document_config(&self, uri: &Url) -> Arc<Config> {
+        self.try_document_config(uri).expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+    }
+
+    /// Like [`Self::document_config`], for request handlers.
+    fn try_document_config(&self, uri: &Url) -> Result<Arc<Config>> {
+        let effective = lock(&self.effective_configs)?;
+        let folders = lock(&self.folders)?;
+        let folder =
+            folder_of(&folders, uri).and_then(|folder| effective.folders.get(&folder.name));
+
+        Ok(folder.unwrap_or(&effective.base).clone())
+    }
+
+    /// Compute the settings of the documents of each of `folders` from `config`, once for every
+    /// request reading them, after the settings or the folders changed.
+    fn update_effective_configs(&self, config: &Config, folders: &[WorkspaceFolder]) {
+        let folders = folders
+            .iter()
+            .filter(|folder| config.folder_settings.contains_key(&folder.name))
+            .map(|folder| {
+                let effective = config.for_folder(Some(&folder.name)).into_owned();
+                (folder.name.clone(), Arc::new(effective))
+            })
+            .collect();
+
+        *self.effective_configs.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG) =
+            EffectiveConfigs { base: Arc::new(config.clone()), folders };
+    }
+
+    /// The local paths of the workspace folders.
+    fn roots(&self) -> Vec<PathBuf> {
+        self.folders
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .iter()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect()
+    }
+
+    /// Run `task` in the background until it completes or the server stops.
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut tasks = self.tasks.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(tokio::spawn(task));
+    }
+
+    /// Stop the background tasks and save the index snapshot, once. Called on `shutdown`, and
+    /// should be called once the server is done serving in case the client exited without
+    /// asking it to shut down first.
+    pub async fn stop(&self) {
+        if self.stopped.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        for task in self.tasks.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).drain(..) {
+            task.abort();
+        }
+        for (_, timer) in self
+            .diagnostic_timers
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .drain()
+        {
+            timer.abort();
+        }
+        self.save_snapshot().await;
+    }
+
+    /// Record activity, postponing the idle timeout.
+    fn touch(&self) {
+        *self.last_activity.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG) = Instant::now();
+    }
+
+    /// Release memory that is not needed while the server sits idle: documents that have
+    /// already been closed are dropped.
+    fn trim_caches(&self) {
+        let mut documents = self.documents.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+        documents.retain(|_, document| document.open);
+        documents.shrink_to_fit();
+    }
+
+    /// Trim caches once the server has been idle for the configured `idleTimeout`. Runs for the
+    /// lifetime of the server.
+    async fn watch_idle(self) {
+        let mut last_trim: Option<Instant> = None;
+
+        loop {
+            let idle_timeout = self.config.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).idle_timeout;
+            let Some(idle_timeout) = idle_timeout.map(Duration::from_secs) else {
+                tokio::time::sleep(IDLE_POLL_INTERVAL).await;
+                continue;
+            };
+
+            let last_activity = *self.last_activity.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+            let idle = last_activity.elapsed();
+
+            if idle < idle_timeout {
+                tokio::time::sleep(idle_timeout - idle).await;
+                continue;
+            }
+
+            if last_trim.is_none_or(|trimmed_at| trimmed_at < last_activity) {
+                self.trim_caches();
+                last_trim = Some(Instant::now());
+                self.client
+                    .log_message(MessageType::INFO, "idle, caches trimmed")
+                    .await;
+            }
+
+            tokio::time::sleep(idle_timeout).await;
+        }
+    }
+
+    /// Drop the cached completions of every document, for changes that affect them all.
+    fn clear_completion_caches(&self) {
+        for document in self.documents.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).values_mut() {
+            document.completion_cache.clear();
+        }
+    }
+
+    /// Like [`Self::clear_completion_caches`], but only when completions depend on other
+    /// documents than the one being completed.
+    fn clear_shared_completion_caches(&self) {
+        if self
+            .config
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .complete_from_all_documents
+        {
+            self.clear_completion_caches();
+        }
+    }
+
+    /// Compute the completions for `params`, giving up with `None` once `cancelled` is set.
+    fn compute_completion(
+        &self,
+        params: CompletionParams,
+        cancelled: &AtomicBool,
+    ) -> Result<Option<CompletionResponse>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let mut documents = lock(&self.documents)?;
+        let document = documents.get(&uri).ok_or_else(|| unknown_document(&uri))?;
+
+        if !document.indexed {
+            return Ok(Some(CompletionResponse::Array(Vec::new())));
+        }
+
+        let config = self.try_document_config(&uri)?;
+
+        let root = {
+            let folders = lock(&self.folders)?;
+            folder_of(&folders, &uri)
+                .or(folders.first())
+                .and_then(|folder| folder.uri.to_file_path().ok())
+        };
+        if let Some(root) = root.filter(|_| config.path_completion) {
+            let line = get_line(&document.text, position.line as usize).unwrap_or_default();
+            let (before_cursor, after_cursor) = line.split_at(get_byte_offset_in_line(
+                line,
+                position.character,
+                PositionEncoding::Utf16,
+            ));
+            let mut cache = lock(&self.directory_cache)?;
+            let commit_on_slash =
+                config.path_commit_characters && lock(&self.completion_support)?.commit_characters;
+
+            if let Some(list) = complete_path(
+                &root,
+                before_cursor,
+                after_cursor,
+                position,
+                commit_on_slash,
+                config.collation,
+                &mut cache,
+            ) {
+                return Ok(Some(CompletionResponse::List(list)));
+            }
+        }
+
+        let key = CacheKey {
+            position,
+            line: get_line(&document.text, position.line as usize)
+                .unwrap_or_default()
+                .to_string(),
+            prefix: find_word_before_cursor(
+                &document.text,
+                position,
+                self.tokenizer.as_ref(),
+                &config.tokenizer,
+            ),
+        };
+
+        if let Some(list) = document.completion_cache.get(&key) {
+            return Ok(Some(CompletionResponse::List(list.clone())));
+        }
+
+        let workspace_files = lock(&self.workspace_files)?;
+        let wordlist = lock(&self.wordlist)?;
+        let kill_ring = lock(&self.kill_ring)?;
+        let vocabulary = lock(&self.vocabulary)?;
+        let others = other_sources(
+            &uri,
+            &documents,
+            &workspace_files,
+            vocabulary.ranked(),
+            &wordlist,
+            &kill_ring,
+            &config,
+        );
+        let Some(list) = complete_document(
+            document,
+            position,
+            self.tokenizer.as_ref(),
+            &config,
+            *lock(&self.completion_support)?,
+            &others,
+            cancelled,
+        ) else {
+            return Ok(None);
+        };
+
+        if let Some(document) = documents.get_mut(&uri) {
+            document.completion_cache.insert(key, list.clone());
+        }
+
+        Ok(Some(CompletionResponse::List(list)))
+    }
+
+    /// Remember `deleted` words, most recent first, keeping at most `size` of them.
+    fn push_to_kill_ring(&self, deleted: Vec<String>, size: usize) {
+        let mut kill_ring = self.kill_ring.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+
+        for word in deleted {
+            kill_ring.retain(|other| *other != word);
+            kill_ring.insert(0, word);
+        }
+        kill_ring.truncate(size);
+    }
+
+    /// Publish the diagnostics of `uri`, or clear them when the document is closed or
+    /// `maxLineLength` is unset. Nothing is sent when there were and still are none, as with
+    /// the default settings.
+    async fn publish_diagnostics(&self, uri: Url) {
+        let max_line_length = self.document_config(&uri).max_line_length;
+        let Some((diagnostics, version)) = self
+            .documents
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .get(&uri)
+            .map(|document| {
+                let diagnostics = max_line_length
+                    .filter(|_| document.open)
+                    .map(|max| {
+                        line_length_diagnostics(&document.text, max, PositionEncoding::Utf16)
+                    })
+                    .unwrap_or_default();
+                (diagnostics, document.version)
+            })
+        else {
+            return;
+        };
+
+        let publish = {
+            let mut diagnosed = self.diagnosed.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+            if diagnostics.is_empty() {
+                diagnosed.remove(&uri)
+            } else {
+                diagnosed.insert(uri.clone());
+                true
+            }
+        };
+        if !publish {
+            return;
+        }
+
+        self.client.publish_diagnostics(uri, diagnostics, Some(version)).await;
+    }
+
+    /// Add the words of `text` to the global vocabulary, which every document completes from.
+    fn see_in_vocabulary(&self, text: &str, config: &Config) {
+        self.vocabulary.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).see(
+            text,
+            self.tokenizer.as_ref(),
+            &config.tokenizer,
+            config.vocabulary_half_life,
+        );
+        self.clear_completion_caches();
+    }
+
+    /// Publish the diagnostics of `uri` once `delay` passed without another call for it, so they
+    /// do not flicker while the user types.
+    fn publish_diagnostics_later(&self, uri: Url, delay: Duration) {
+        let backend = self.clone();
+        let timer = tokio::spawn({
+            let uri = uri.clone();
+            async move {
+                tokio::time::sleep(delay).await;
+                backend.publish_diagnostics(uri).await;
+            }
+        });
+
+        let mut timers = self.diagnostic_timers.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+        timers.retain(|_, timer| !timer.is_finished());
+        if let Some(previous) = timers.insert(uri, timer) {
+            previous.abort();
+        }
+    }
+
+    /// (Re)load the words of `wordlistPath`, one per line.
+    async fn load_wordlist(&self) {
+        let path = self
+            .config
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .wordlist_path
+            .clone();
+
+        let words = match path.map(std::fs::read_to_string).transpose() {
+            Ok(contents) => contents
+                .unwrap_or_default()
+                .lines()
+                .map(str::trim)
+                .filter(|word| !word.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(err) => {
+                self.client
+                    .log_message(MessageType::WARNING, format!("failed to read wordlist: {err}"))
+                    .await;
+                Vec::new()
+            }
+        };
+
+        *self.wordlist.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG) = words;
+        self.clear_completion_caches();
+    }
+
+    /// Read the snapshot at `indexSnapshotPath`, if set.
+    async fn load_snapshot(&self) {
+        let config = self.config.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).clone();
+        let Some(path) = config.index_snapshot_path else {
+            return;
+        };
+
+        let snapshot = match Snapshot::load(&path, &config.tokenizer) {
+            Ok(snapshot) => snapshot,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                Snapshot::new(config.tokenizer.clone())
+            }
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("failed to read index snapshot {}: {err}", path.display()),
+                    )
+                    .await;
+                Snapshot::new(config.tokenizer.clone())
+            }
+        };
+        let count = snapshot.len();
+
+        *self.snapshot.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG) = Some(snapshot);
+        self.client
+            .log_message(MessageType::INFO, format!("read the index of {count} documents"))
+            .await;
+    }
+
+    /// Save the indices of the open documents to `indexSnapshotPath`, if set.
+    async fn save_snapshot(&self) {
+        let config = self.config.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).clone();
+        let Some(path) = config.index_snapshot_path else {
+            return;
+        };
+
+        let mut snapshot = Snapshot::new(config.tokenizer.clone());
+        for (uri, document) in self.documents.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).iter() {
+            // Documents split with the tokenizer settings of their folder would be stale
+            if let Some(occurrences) = document.occurrences.as_ref().filter(|_| {
+                document.open && self.document_config(uri).tokenizer == config.tokenizer
+            }) {
+                snapshot.insert(uri.clone(), &document.text, occurrences);
+            }
+        }
+
+        if let Err(err) = snapshot.save(&path) {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to save index snapshot {}: {err}", path.display()),
+                )
+                .await;
+        }
+    }
+
+    /// Read the files of the workspace folders in the background, `indexingConcurrency` at a
+    /// time. Their words are offered as soon as each file is read.
+    async fn index_workspace(self) {
+        let roots = self.roots();
+        let concurrency = self
+            .config
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .indexing_concurrency
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from))
+            .max(1);
+        let Ok(paths) = tokio::task::spawn_blocking(move || list_workspace(&roots)).await else {
+            return;
+        };
+
+        // Dropping the set, as when the server stops, aborts the reads still pending
+        let permits = Arc::new(Semaphore::new(concurrency));
+        let mut tasks = JoinSet::new();
+        for path in paths {
+            let permits = permits.clone();
+            let backend = self.clone();
+
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await.ok()?;
+                let file = tokio::task::spawn_blocking(move || read_workspace_file(&path))
+                    .await
+                    .ok()??;
+
+                backend
+                    .workspace_files
+                    .lock()
+                    .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+                    .push(file);
+                backend.clear_completion_caches();
+                Some(())
+            });
+        }
+
+        let mut count = 0;
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(Some(())) = result {
+                count += 1;
+            }
+        }
+
+        self.client
+            .log_message(MessageType::INFO, format!("read {count} workspace files"))
+            .await;
+    }
+
+    async fn update_config(&self, settings: Value) {
+        let (mut errors, previous, config) = {
+            let mut config = self.config.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+            let previous = config.clone();
+            (config.merge(settings), previous, config.clone())
+        };
+        let folders = self.folders.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).clone();
+        self.update_effective_configs(&config, &folders);
+
+        if config.folder_settings != previous.folder_settings {
+            for (folder, settings) in &config.folder_settings {
+                errors.extend(
+                    config
+                        .clone()
+                        .merge(settings.clone())
+                        .into_iter()
+                        .map(|err| format!("folderSettings.{folder}.{err}")),
+                );
+            }
+        }
+        self.refresh_documents((&previous, &folders), (&config, &folders))
+            .await;
+
+        for err in errors {
+            self.client
+                .log_message(MessageType::WARNING, format!("invalid configuration: {err}"))
+                .await;
+        }
+
+        if config.lightweight && !previous.lightweight {
+            self.client
+                .log_message(
+                    MessageType::INFO,
+                    "lightweight mode: documents are not indexed, so hover only shows definitions \
+                     and rename, trigramIndex and recencyWeight are disabled",
+                )
+                .await;
+        }
+
+        self.load_wordlist().await;
+    }
+
+    /// Bring the documents in line with a change of settings or workspace folders, from the
+    /// `previous` settings and folders to the `current` ones: documents whose effective settings
+    /// now split or index them differently are indexed again, and their diagnostics published
+    /// again when their limit changed.
+    async fn refresh_documents(
+        &self,
+        previous: (&Config, &[WorkspaceFolder]),
+        current: (&Config, &[WorkspaceFolder]),
+    ) {
+        let effective = |(config, folders): (&Config, &[WorkspaceFolder]), uri: &Url| {
+            let folder = folder_of(folders, uri).map(|folder| folder.name.clone());
+            config.for_folder(folder.as_deref()).into_owned()
+        };
+        let mut stale_diagnostics = Vec::new();
+
+        for (uri, document) in self.documents.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).iter_mut() {
+            let (before, after) = (effective(previous, uri), effective(current, uri));
+
+            if before.index_differs(&after) {
+                document.reindex(self.tokenizer.as_ref(), &after);
+            }
+            if document.open && before.max_line_length != after.max_line_length {
+                stale_diagnostics.push(uri.clone());
+            }
+        }
+
+        for uri in stale_diagnostics {
+            self.publish_diagnostics(uri).await;
+        }
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        #[allow(deprecated)]
+        let folders = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) if !folders.is_empty() => folders,
+            // Older clients only send a root, named like a folder would be
+            (_, root_uri) => root_uri
+                .map(|uri| WorkspaceFolder {
+                    name: uri
+                        .path_segments()
+                        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+                        .unwrap_or_default()
+                        .to_string(),
+                    uri,
+                })
+                .into_iter()
+                .collect(),
+        };
+        *lock(&self.folders)? = folders;
+        if let Some(options) = params.initialization_options {
+            self.update_config(options).await;
+        }
+        self.load_snapshot().await;
+
+        let completion = params.capabilities.text_document.and_then(|t| t.completion);
+        let completion_item = completion.as_ref().and_then(|c| c.completion_item.as_ref());
+        let completion_support = CompletionSupport {
+            label_details: completion_item
+                .and_then(|item| item.label_details_support)
+                .unwrap_or_default(),
+            insert_replace: completion_item
+                .and_then(|item| item.insert_replace_support)
+                .unwrap_or_default(),
+            deprecated_tag: completion_item
+                .and_then(|item| item.tag_support.as_ref())
+                .is_some_and(|tags| tags.value_set.contains(&CompletionItemTag::DEPRECATED)),
+            commit_characters: completion_item
+                .and_then(|item| item.commit_characters_support)
+                .unwrap_or_default(),
+            // The protocol has no such capability, clients that want one send it as experimental
+            max_items: params
+                .capabilities
+                .experimental
+                .as_ref()
+                .and_then(|experimental| experimental.get("maxCompletionItems")?.as_u64())
+                .map(|max| max as usize),
+            item_defaults: completion
+                .as_ref()
+                .and_then(|c| c.completion_list.as_ref()?.item_defaults.as_deref())
+                .map(ItemDefaultsSupport::new)
+                .unwrap_or_default(),
+        };
+        *lock(&self.completion_support)? = completion_support;
+
+        let config = lock(&self.config)?.clone();
+
+        Ok(InitializeResult {
+            server_info: None,
+            capabilities: server_capabilities(&config, completion_support.commit_characters),
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.spawn(self.clone().watch_idle());
+
+        if self
+            .config
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .complete_from_workspace
+        {
+            self.spawn(self.clone().index_workspace());
+        }
+
+        self.client.log_message(MessageType::INFO, "initialized!").await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        self.stop().await;
+
+        Ok(())
+    }
+
+    async fn did_open(&self, mut params: DidOpenTextDocumentParams) {
+        self.touch();
+
+        let uri = params.text_document.uri;
+        let config = self.document_config(&uri);
+        let indexed = config.schemes.iter().any(|scheme| scheme == uri.scheme());
+
+        if config.single_line {
+            params.text_document.text = join_lines(params.text_document.text);
+        }
+
+        let snapshot = self
+            .snapshot
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .as_mut()
+            .filter(|_| !config.lightweight)
+            .and_then(|snapshot| {
+                snapshot.take(
+                    &uri,
+                    &params.text_document.text,
+                    &config.tokenizer,
+                    config.max_occurrences_tracked,
+                )
+            });
+        let mut document = match snapshot {
+            Some(offsets) => Document::with_occurrences(
+                params.text_document.text,
+                params.text_document.version,
+                params.text_document.language_id,
+                indexed,
+                Some(OccurrenceIndex::from_offsets(
+                    offsets,
+                    config.trigram_index,
+                    config.max_occurrences_tracked,
+                )),
+            ),
+            None => Document::new(
+                params.text_document.text,
+                params.text_document.version,
+                params.text_document.language_id,
+                indexed,
+                self.tokenizer.as_ref(),
+                &config,
+            ),
+        };
+
+        document.single_line = config.single_line;
+
+        if config.global_vocabulary && indexed {
+            self.see_in_vocabulary(&document.text, &config);
+        }
+
+        // A repeated open, as some clients send on reload, replaces the whole document: text,
+        // version, index and caches
+        let previous = self
+            .documents
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .insert(uri.clone(), document);
+
+        self.clear_shared_completion_caches();
+        self.publish_diagnostics(uri.clone()).await;
+
+        if previous.is_some_and(|previous| previous.open) {
+            self.client
+                .log_message(MessageType::LOG, format!("{uri} opened again, resetting it"))
+                .await;
+        }
+        self.client.log_message(MessageType::INFO, "file opened!").await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        self.touch();
+        self.vocabulary.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).tick();
+
+        let config = self.document_config(&params.text_document.uri);
+        let version = params.text_document.version;
+        let mut previous_version = None;
+        let mut deleted = Vec::new();
+
+        if let Some(document) = self
+            .documents
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .get_mut(&params.text_document.uri)
+        {
+            previous_version = Some(document.version);
+            let edited = document.apply_changes(
+                version,
+                params.content_changes,
+                self.tokenizer.as_ref(),
+                &config.tokenizer,
+                (config.kill_ring_size > 0).then_some(&mut deleted),
+            );
+            match edited {
+                Some(edited) => {
+                    document
+                        .completion_cache
+                        .invalidate(&edited, &config, &document.language_id)
+                }
+                None => document.completion_cache.clear(),
+            }
+        }
+
+        if deleted.is_empty() {
+            self.clear_shared_completion_caches();
+        } else {
+            self.push_to_kill_ring(deleted, config.kill_ring_size);
+            self.clear_completion_caches();
+        }
+        // Without `maxLineLength` there is nothing to diagnose, and a change of it already
+        // cleared stale diagnostics
+        if config.max_line_length.is_some() {
+            match config.diagnostics_debounce {
+                0 => self.publish_diagnostics(params.text_document.uri.clone()).await,
+                delay => self.publish_diagnostics_later(
+                    params.text_document.uri.clone(),
+                    Duration::from_millis(delay),
+                ),
+            }
+        }
+
+        // A gap means a change was lost or reordered, so the text may no longer match the client's
+        if let Some(previous) = previous_version.filter(|&previous| previous + 1 != version) {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!(
+                        "{} went from version {previous} to {version}, the text may be out of sync",
+                        params.text_document.uri
+                    ),
+                )
+                .await;
+        }
+        self.client.log_message(MessageType::INFO, "file changed!").await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.touch();
+
+        let config = self.document_config(&params.text_document.uri);
+        let mut text = None;
+        if let Some(document) = self
+            .documents
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .get_mut(&params.text_document.uri)
+        {
+            document.open = false;
+            // Its words as last edited
+            text = (config.global_vocabulary && document.indexed).then(|| document.text.clone());
+        }
+
+        if let Some(text) = text {
+            self.see_in_vocabulary(&text, &config);
+        }
+        self.clear_shared_completion_caches();
+        self.publish_diagnostics(params.text_document.uri).await;
+
+        self.client.log_message(MessageType::INFO, "file closed!").await;
+    }
+
+    async fn completion(&self, mut params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        self.touch();
+
+        let position = &mut params.text_document_position;
+        position.position = self
+            .clamped_position(&position.text_document.uri, position.position)
+            .await?;
+
+        // tower-lsp drops this future when the client cancels the request, the guard then tells
+        // the computation running on the blocking pool to give up.
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(cancelled.clone());
+        let backend = self.clone();
+
+        tokio::task::spawn_blocking(move || backend.compute_completion(params, &cancelled))
+            .await
+            .map_err(|err| internal_error(format!("completion failed: {err}")))?
+    }
+
+    async fn hover(&self, mut params: HoverParams) -> Result<Option<Hover>> {
+        self.touch();
+
+        let position = &mut params.text_document_position_params;
+        position.position = self
+            .clamped_position(&position.text_document.uri, position.position)
+            .await?;
+
+        let uri = &params.text_document_position_params.text_document.uri;
+        let documents = lock(&self.documents)?;
+        let document = documents.get(uri).ok_or_else(|| unknown_document(uri))?;
+        let config = self.try_document_config(uri)?;
+
+        Ok(hover(
+            document,
+            params.text_document_position_params.position,
+            self.tokenizer.as_ref(),
+            &config,
+        ))
+    }
+
+    async fn document_color(&self, params: DocumentColorParams) -> Result<Vec<ColorInformation>> {
+        self.touch();
+
+        let uri = &params.text_document.uri;
+        if !self.try_document_config(uri)?.document_colors {
+            return Ok(Vec::new());
+        }
+
+        let documents = lock(&self.documents)?;
+
+        Ok(documents
+            .get(uri)
+            .map(|document| document_colors(&document.text))
+            .unwrap_or_default())
+    }
+
+    async fn color_presentation(
+        &self,
+        params: ColorPresentationParams,
+    ) -> Result<Vec<ColorPresentation>> {
+        Ok(color_presentations(params.color, params.range))
+    }
+
+    async fn rename(&self, mut params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        self.touch();
+
+        let position = &mut params.text_document_position;
+        position.position = self
+            .clamped_position(&position.text_document.uri, position.position)
+            .await?;
+
+        let uri = params.text_document_position.text_document.uri;
+        let documents = lock(&self.documents)?;
+        let document = documents.get(&uri).ok_or_else(|| unknown_document(&uri))?;
+        let config = self.try_document_config(&uri)?;
+
+        let edits = rename(
+            document,
+            params.text_document_position.position,
+            &params.new_name,
+            self.tokenizer.as_ref(),
+            &config,
+        );
+
+        Ok(edits.map(|edits| WorkspaceEdit::new(HashMap::from([(uri, edits)]))))
+    }
+
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        let config = self.config.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG).clone();
+        let (previous, current) = {
+            let mut folders = self.folders.lock().expect(FAILED_TO_ACQUIRE_LOCK_MSG);
+            let previous = folders.clone();
+            folders.retain(|folder| !params.event.removed.contains(folder));
+            folders.extend(params.event.added);
+            (previous, folders.clone())
+        };
+        self.update_effective_configs(&config, &current);
+
+        self.refresh_documents((&config, &previous), (&config, &current))
+            .await;
+        self.clear_completion_caches();
+
+        self.client
+            .log_message(MessageType::INFO, "workspace folders changed!")
+            .await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        self.update_config(params.settings).await;
+
+        self.client
+            .log_message(MessageType::INFO, "configuration changed!")
+            .await;
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        let wordlist_path = self
+            .config
+            .lock()
+            .expect(FAILED_TO_ACQUIRE_LOCK_MSG)
+            .wordlist_path
+            .clone();
+        let wordlist_changed = wordlist_path.is_some_and(|path| {
+            params
+                .changes
+                .iter()
+                .any(|change| change.uri.to_file_path().is_ok_and(|changed| changed == path))
+        });
+
+        if wordlist_changed {
+            self.load_wordlist().await;
+        }
+        self.client
+            .log_message(MessageType::INFO, "watched files have changed!")
+            .await;
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        self.touch();
+
+        let documents = lock(&self.documents)?;
+        let configs = documents
+            .iter()
+            .filter(|(_, document)| document.open)
+            .map(|(uri, _)| Ok((uri, self.try_document_config(uri)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Some(workspace_symbols(&params.query, &documents, &configs, self.tokenizer.as_ref())))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        self.touch();
+
+        match params.command.as_str() {
+            command::TOKENIZE => {
+                let arguments: TokenizeArguments = parse_arguments(params.arguments)?;
+                let uri = &arguments.text_document.uri;
+                let documents = lock(&self.documents)?;
+                let document = documents.get(uri).ok_or_else(|| unknown_document(uri))?;
+                let config = self.try_document_config(uri)?;
+                let tokens = tokenize(
+                    &document.text,
+                    arguments.range,
+                    self.tokenizer.as_ref(),
+                    &config.tokenizer,
+                );
+
+                Ok(serde_json::to_value(tokens).ok())
+            }
+            command::RESET_DOCUMENT => {
+                let TextDocumentIdentifier { uri } = parse_arguments(params.arguments)?;
+                if lock(&self.documents)?.remove(&uri).is_none() {
+                    return Err(unknown_document(&uri));
+                }
+
+                self.clear_shared_completion_caches();
+                self.client
+                    .log_message(MessageType::INFO, format!("forgot the text and index of {uri}"))
+                    .await;
+                self.client
+                    .show_message(
+                        MessageType::INFO,
+                        format!("Reopen {uri} to synchronize it again"),
+                    )
+                    .await;
+
+                Ok(None)
+            }
+            command::REBUILD_INDEX => {
+                let uri = if params.arguments.is_empty() {
+                    None
+                } else {
+                    Some(parse_arguments::<TextDocumentIdentifier>(params.arguments)?.uri)
+                };
+                let mut documents = lock(&self.documents)?;
+
+                match &uri {
+                    Some(uri) => {
+                        let document =
+                            documents.get_mut(uri).ok_or_else(|| unknown_document(uri))?;
+                        let config = self.try_document_config(uri)?;
+                        document.reindex(self.tokenizer.as_ref(), &config);
+                    }
+                    None => {
+                        for (uri, document) in documents.iter_mut() {
+                            let config = self.try_document_config(uri)?;
+                            document.reindex(self.tokenizer.as_ref(), &config);
+                        }
+                    }
+                }
+
+                Ok(None)
+            }
+            command::SET_SELECTION => {
+                let arguments: SetSelectionArguments = parse_arguments(params.arguments)?;
+                let uri = &arguments.text_document.uri;
+                let mut documents = lock(&self.documents)?;
+                let document = documents.get_mut(uri).ok_or_else(|| unknown_document(uri))?;
+                let config = self.try_document_config(uri)?;
+
+                // Words partly selected count too, so split the whole lines of the selection
+                document.selected_words = arguments
+                    .range
+                    .map(|range| {
+                        let lines = Range::new(
+                            Position::new(range.start.line, 0),
+                            Position::new(range.end.line + 1, 0),
+                        );
+                        tokenize(&document.text, lines, self.tokenizer.as_ref(), &config.tokenizer)
+                            .into_iter()
+                            .filter(|token| {
+                                token.category == CharCategory::Word
+                                    && token.range.end > range.start
+                                    && token.range.start < range.end
+                            })
+                            .map(|token| token.text.to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                document.completion_cache.clear();
+
+                Ok(None)
+            }
+            command::COMPLETE => {
+                let arguments: CompleteArguments = parse_arguments(params.arguments)?;
+                let uri = arguments.text_document.uri;
+                let documents = lock(&self.documents)?;
+                let document = documents.get(&uri).ok_or_else(|| unknown_document(&uri))?;
+                let config = self.try_document_config(&uri)?;
+                let workspace_files = lock(&self.workspace_files)?;
+                let wordlist = lock(&self.wordlist)?;
+                let kill_ring = lock(&self.kill_ring)?;
+                let vocabulary = lock(&self.vocabulary)?;
+                let others = other_sources(
+                    &uri,
+                    &documents,
+                    &workspace_files,
+                    vocabulary.ranked(),
+                    &wordlist,
+                    &kill_ring,
+                    &config,
+                );
+
+                let labels = complete_prefix(
+                    document,
+                    &arguments.prefix,
+                    self.tokenizer.as_ref(),
+                    &config,
+                    *lock(&self.completion_support)?,
+                    &others,
+                );
+
+                Ok(serde_json::to_value(labels).ok())
+            }
+            command::DOCUMENTS => {
+                let documents = lock(&self.documents)?;
+                let mut list = documents
+                    .iter()
+                    .map(|(uri, document)| {
+                        Ok(DocumentInfo {
+                            uri,
+                            version: document.version,
+                            language_id: &document.language_id,
+                            word_count: document.word_count(
+                                self.tokenizer.as_ref(),
+                                &self.try_document_config(uri)?.tokenizer,
+                            ),
+                            open: document.open,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                list.sort_by_key(|info| info.uri.as_str());
+
+                Ok(serde_json::to_value(list).ok())
+            }
+            command => Err(jsonrpc::Error::invalid_params(format!("unknown command {command}"))),
+        }
+    }
+}
+
+/// The [`LspService`] of a [`Backend`], sending completion lists with `itemDefaults` to the
+/// clients that support them.
+///
+/// [`LanguageServer::completion`] can only return the lsp-types `CompletionList`, which predates
+/// `itemDefaults`, so they are applied to the serialized response instead. Serve this rather
+/// than the bare [`LspService`].
+pub struct BackendService {
+    inner: LspService<Backend>,
+}
+
+impl BackendService {
+    pub fn new(inner: LspService<Backend>) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &Backend {
+        self.inner.inner()
+    }
+}
+
+impl Service<jsonrpc::Request> for BackendService {
+    type Response = Option<jsonrpc::Response>;
+    type Error = ExitedError;
+    type Future = Pin<Box<dyn Future<Output = StdResult<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<StdResult<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: jsonrpc::Request) -> Self::Future {
+        // Without the lock, the client gets the list without defaults, which is just as valid
+        let item_defaults = (request.method() == "textDocument/completion")
+            .then(|| self.inner().completion_support.lock().ok())
+            .flatten()
+            .map(|support| support.item_defaults);
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            let Some(support) = item_defaults else {
+                return Ok(response);
+            };
+
+            Ok(response.map(|response| {
+                let (id, result) = response.into_parts();
+                let result = result.map(|list| with_item_defaults(list, support));
+                jsonrpc::Response::from_parts(id, result)
+            }))
+        })
+    }
+}
+
+/// The capabilities advertised with `config` to a client, which supports commit characters or
+/// not.
+pub fn server_capabilities(config: &Config, commit_characters_support: bool) -> ServerCapabilities {
+    let commit_characters = commit_characters_support && !config.commit_characters.is_empty();
+
+    // Only advertise what is implemented: clients do not send requests for unadvertised
+    // capabilities, which would otherwise fail or get empty responses. Every capability left
+    // to the default is handled by the `tower_lsp` default, which fails with method not found.
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+            open_close: Some(true),
+            change: Some(TextDocumentSyncKind::INCREMENTAL),
+            ..TextDocumentSyncOptions::default()
+        })),
+        completion_provider: Some(CompletionOptions {
+            resolve_provider: Some(false),
+            work_done_progress_options: Default::default(),
+            all_commit_characters: commit_characters.then(|| config.commit_characters.clone()),
+            ..Default::default()
+        }),
+        // Without an index, hover can only show definitions and rename does nothing
+        hover_provider: (!config.lightweight || config.hover_definitions)
+            .then_some(HoverProviderCapability::Simple(true)),
+        rename_provider: (!config.lightweight).then_some(OneOf::Left(true)),
+        workspace_symbol_provider: Some(OneOf::Left(true)),
+        workspace: Some(WorkspaceServerCapabilities {
+            workspace_folders: Some(WorkspaceFoldersServerCapabilities {
+                supported: Some(true),
+                change_notifications: Some(OneOf::Left(true)),
+            }),
+            file_operations: None,
+        }),
+        execute_command_provider: Some(ExecuteCommandOptions {
+            commands: command::COMMANDS.iter().map(ToString::to_string).collect(),
+            ..ExecuteCommandOptions::default()
+        }),
+        color_provider: config
+            .document_colors
+            .then_some(ColorProviderCapability::Simple(true)),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// The sources completions of `uri` take words from besides the document itself, as enabled
+/// in `config`.
+fn other_sources<'a>(
+    uri: &Url,
+    documents: &'a HashMap<Url, Document>,
+    workspace_files: &'a [WorkspaceFile],
+    vocabulary: &'a [String],
+    wordlist: &'a [String],
+    kill_ring: &'a [String],
+    config: &Config,
+) -> Vec<Source<'a>> {
+    let mut others = Vec::new();
+    if config.complete_from_all_documents {
+        others.extend(
+            documents
+                .iter()
+                .filter(|(other, document)| *other != uri && document.open && document.indexed)
+                .map(|(uri, document)| Source {
+                    name: uri.path_segments().and_then(Iterator::last).unwrap_or(uri.as_str()),
+                    words: Words::Text(&document.text),
+                }),
+        );
+    }
+
+    if config.complete_from_workspace {
+        // Documents known to the client are more recent than what was read from disk
+        others.extend(
+            workspace_files
+                .iter()
+                .filter(|file| !documents.contains_key(&file.uri))
+                .map(|file| Source { name: &file.name, words: Words::Text(&file.text) }),
+        );
+    }
+
+    if config.global_vocabulary && !vocabulary.is_empty() {
+        others.push(Source { name: "vocabulary", words: Words::List(vocabulary) });
+    }
+
+    if !wordlist.is_empty() {
+        others.push(Source { name: "wordlist", words: Words::List(wordlist) });
+    }
+
+    if config.kill_ring_size > 0 && !kill_ring.is_empty() {
+        others.push(Source { name: "deleted", words: Words::List(kill_ring) });
+    }
+
+    others
+}
+
+/// Lock `mutex` for a request handler. A panic while it was held may have left the state it
+/// guards inconsistent, so the request fails rather than trusting it.
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex
+        .lock()
+        .map_err(|_| internal_error("server state left inconsistent by an earlier panic"))
+}
+
+/// An internal error with a message, which the client can show, unlike the bare
+/// [`jsonrpc::Error::internal_error`].
+fn internal_error(message: impl Into<Cow<'static, str>>) -> jsonrpc::Error {
+    jsonrpc::Error {
+        message: message.into(),
+        ..jsonrpc::Error::internal_error()
+    }
+}
+
+/// The error of a request about a document the client never opened.
+fn unknown_document(uri: &Url) -> jsonrpc::Error {
+    jsonrpc::Error::invalid_params(format!("unknown document {uri}"))
+}
+
+/// Deserialize the single argument of a command.
+fn parse_arguments<T: DeserializeOwned>(arguments: Vec<Value>) -> Result<T> {
+    let argument = arguments.into_iter().next().unwrap_or_default();
+
+    serde_json::from_value(argument)
+        .map_err(|err| jsonrpc::Error::invalid_params(format!("invalid arguments: {err}")))
+}
+
+/// Like [`Tokenizer::split`], but also yields the byte offset of every word in `s`.
+fn split_indices<'a>(
+    s: &'a str,
+    tokenizer: &dyn Tokenizer,
+    config: &TokenizerConfig,
+) -> impl Iterator<Item = (usize, &'a str)> {
+    tokenizer.split(s, config).into_iter().scan(0, |offset, word| {
+        let start = *offset;
+        *offset += word.len();
+        Some((start, word))
+    })
+}
+
+/// Like [`Tokenizer::split`], but also yields the position of every word in `s`.
+fn split_positions<'a>(
+    s: &'a str,
+    tokenizer: &dyn Tokenizer,
+    config: &TokenizerConfig,
+) -> impl Iterator<Item = (Position, &'a str)> {
+    let mut position = Position::new(0, 0);
+    let mut after_carriage_return = false;
+
+    tokenizer.split(s, config).into_iter().map(move |word| {
+        let start = position;
+
+        for ch in word.chars() {
+            match ch {
+                '\n' if after_carriage_return => {}
+                '\n' | '\r' => {
+                    position.line += 1;
+                    position.character = 0;
+                }
+                _ => position.character += ch.len_utf16() as u32,
+            }
+            after_carriage_return = ch == '\r';
+        }
+
+        (start, word)
+    })
+}
+
+/// find a word at the given position, in the given text at current line
+fn find_word_before_cursor(
+    text: &str,
+    position: Position,
+    tokenizer: &dyn Tokenizer,
+    config: &TokenizerConfig,
+) -> String {
+    // Nothing precedes the cursor at the start of a line, be it empty, the first or past the end
+    if position.character == 0 {
+        return String::new();
+    }
+
+    // From the start of the line to the cursor position
+    let text_start_to_cursor = {
+        let current_line = get_line(text, position.line as usize).unwrap_or_default();
+        let byte_offset =
+            get_byte_offset_in_line(current_line, position.character, PositionEncoding::Utf16);
+        current_line.split_at(byte_offset).0
+    };
+
+    tokenizer
+        .split(text_start_to_cursor, config)
+        .last()
+        .copied()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Find the word containing, or ending right at, the given position.
+///
+/// Returns the byte offset of the word in `text` along with the word itself.
+fn find_word_at_position<'a>(
+    text: &'a str,
+    position: Position,
+    tokenizer: &dyn Tokenizer,
+    config: &TokenizerConfig,
+) -> Option<(usize, &'a str)> {
+    let line_start = get_line_start(text, position.line as usize)?;
+    let cursor = get_char_index_from_position(text, position, PositionEncoding::Utf16) - line_start;
+    let line = get_line(text, position.line as usize)?;
+
+    split_indices(line, tokenizer, config)
+        .filter(|(_, word)| {
+            word.chars().last().map(|ch| categorize_char_with(ch, config))
+                == Some(CharCategory::Word)
+        })
+        .find(|(start, word)| (*start..=start + word.len()).contains(&cursor))
+        .map(|(start, word)| (line_start + start, word))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde_json::json;
+    use tower::{Service, ServiceExt};
+    use tower_lsp::{jsonrpc::Request, LspService};
+
+    use super::*;
+    use crate::tokenizer::CategoryTokenizer;
+
+    /// A server initialized with `options`, and the messages it logged so far.
+    ///
+    /// Published diagnostics are recorded as `diagnostics <uri>: <count>`.
+    async fn start(options: Value) -> (LspService<Backend>, Arc<Mutex<Vec<String>>>) {
+        start_with(json!({}), options).await
+    }
+
+    /// Like [`start`], for a client with `capabilities`.
+    async fn start_with(
+        capabilities: Value,
+        options: Value,
+    ) -> (LspService<Backend>, Arc<Mutex<Vec<String>>>) {
+        let (mut service, mut socket) =
+            LspService::new(|client| Backend::new(client, Arc::new(CategoryTokenizer)));
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        tokio::spawn({
+            let logs = logs.clone();
+            async move {
+                while let Some(message) = socket.next().await {
+                    if message.method() == "textDocument/publishDiagnostics" {
+                        let params = message.params().unwrap();
+                        let count = params["diagnostics"].as_array().unwrap().len();
+                        let text = format!("diagnostics {}: {count}", params["uri"]);
+                        logs.lock().unwrap().push(text.replace('"', ""));
+                    } else if let Some(text) =
+                        message.params().and_then(|params| params["message"].as_str())
+                    {
+                        logs.lock().unwrap().push(text.to_string());
+                    }
+                }
+            }
+        });
+
+        let initialize = json!({ "capabilities": capabilities, "initializationOptions": options });
+        send(&mut service, Request::build("initialize").params(initialize).id(1).finish()).await;
+        send(&mut service, Request::build("initialized").params(json!({})).finish()).await;
+
+        (service, logs)
+    }
+
+    async fn send(service: &mut LspService<Backend>, request: Request) {
+        service.ready().await.unwrap().call(request).await.unwrap();
+    }
+
+    /// The labels completed at `position` in `uri`.
+    async fn complete_at(
+        service: &mut LspService<Backend>,
+        uri: &str,
+        position: Position,
+    ) -> Vec<String> {
+        let params = json!({ "textDocument": { "uri": uri }, "position": position });
+        let request = Request::build("textDocument/completion")
+            .params(params)
+            .id(2)
+            .finish();
+        let response = service.ready().await.unwrap().call(request).await.unwrap().unwrap();
+        let items = match serde_json::from_value(response.result().unwrap().clone()).unwrap() {
+            Some(CompletionResponse::Array(items)) => items,
+            Some(CompletionResponse::List(list)) => list.items,
+            None => Vec::new(),
+        };
+
+        items.into_iter().map(|item| item.label).collect()
+    }
+
+    async fn open(service: &mut LspService<Backend>, uri: &str, version: i32, text: &str) {
+        let params = json!({
+            "textDocument": { "uri": uri, "languageId": "plaintext", "version": version, "text": text },
+        });
+        send(service, Request::build("textDocument/didOpen").params(params).finish()).await;
+    }
+
+    async fn change(service: &mut LspService<Backend>, uri: &str, version: i32, text: &str) {
+        let params = json!({
+            "textDocument": { "uri": uri, "version": version },
+            "contentChanges": [{ "text": text }],
+        });
+        send(service, Request::build("textDocument/didChange").params(params).finish()).await;
+    }
+
+    /// Whether a message containing `needle` is logged within a second.
+    async fn logged(logs: &Mutex<Vec<String>>, needle: &str) -> bool {
+        for _ in 0..100 {
+            if logs.lock().unwrap().iter().any(|message| message.contains(needle)) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn reopening_a_document_resets_it() {
+        let (mut service, _) = start(json!({})).await;
+
+        open(&mut service, "file:///a.txt", 1, "stale words").await;
+        open(&mut service, "file:///a.txt", 5, "fresh text").await;
+
+        let uri = Url::parse("file:///a.txt").unwrap();
+        let documents = service.inner().documents.lock().unwrap();
+        let document = &documents[&uri];
+        assert_eq!(document.text, "fresh text");
+        assert_eq!(document.version, 5);
+        let occurrences = document.occurrences.as_ref().unwrap();
+        assert_eq!(occurrences.count("stale"), 0);
+        assert_eq!(occurrences.count("words"), 0);
+        assert_eq!(occurrences.get("fresh"), Some(&[0][..]));
+    }
+
+    #[tokio::test]
+    async fn changes_skipping_a_version_are_detected() {
+        let (mut service, logs) = start(json!({})).await;
+
+        open(&mut service, "file:///a.txt", 1, "one").await;
+        change(&mut service, "file:///a.txt", 2, "two").await;
+        assert!(logged(&logs, "file changed!").await);
+        assert!(!logs
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|message| message.contains("out of sync")));
+
+        change(&mut service, "file:///a.txt", 5, "five").await;
+        change(&mut service, "file:///a.txt", 4, "four").await;
+
+        assert!(logged(&logs, "went from version 2 to 5, the text may be out of sync").await);
+        assert!(logged(&logs, "went from version 5 to 4, the text may be out of sync").await);
+    }
+
+    #[test]
+    fn mixed_line_endings_start_lines_where_they_end() {
+        let text = "one\ntwo\r\nthree\rfour";
+
+        assert_eq!(
+            lines_with_offsets(text).collect::<Vec<_>>(),
+            [(0, "one"), (4, "two"), (9, "three"), (15, "four")]
+        );
+        assert_eq!(
+            (0..5).map(|line| get_line_start(text, line)).collect::<Vec<_>>(),
+            [Some(0), Some(4), Some(9), Some(15), None]
+        );
+        let position = Position::new(3, 2);
+        assert_eq!(get_char_index_from_position(text, position, PositionEncoding::Utf16), 17);
+    }
+
+    #[test]
+    fn nothing_precedes_the_cursor_at_the_start_of_a_line() {
+        let word_before = |text, line, character| {
+            let position = Position::new(line, character);
+            find_word_before_cursor(text, position, &CategoryTokenizer, &TokenizerConfig::default())
+        };
+
+        // Start of the document
+        assert_eq!(word_before("", 0, 0), "");
+        assert_eq!(word_before("foo bar", 0, 0), "");
+        // Start of a line
+        assert_eq!(word_before("foo\nbar", 1, 0), "");
+        assert_eq!(word_before("foo\nbar", 1, 2), "ba");
+        // Empty line, even past its end
+        assert_eq!(word_before("foo\n\nbar", 1, 0), "");
+        assert_eq!(word_before("foo\n\nbar", 1, 3), "");
+    }
+
+    #[test]
+    fn graphemes_count_user_perceived_characters() {
+        let graphemes = |s| word_len(s, LengthUnit::Graphemes);
+
+        assert_eq!(graphemes("e\u{301}te\u{301}"), 3);
+        assert_eq!(graphemes("👩\u{200D}💻"), 1);
+        // Hangul syllable spelled with leading, vowel and trailing jamo
+        assert_eq!(graphemes("\u{1100}\u{1161}\u{11A8}"), 1);
+        // Arabic number sign, a prepended character, and the digit it applies to
+        assert_eq!(graphemes("\u{0600}1"), 1);
+        // Two flags from four regional indicators
+        assert_eq!(graphemes("🇫🇷🇩🇪"), 2);
+    }
+
+    #[tokio::test]
+    async fn diagnostics_are_only_published_when_there_are_some_to_show_or_clear() {
+        let (mut service, logs) = start(json!({})).await;
+        open(&mut service, "file:///quiet.txt", 1, "a rather long line").await;
+        change(&mut service, "file:///quiet.txt", 2, "another long line").await;
+        assert!(!logged(&logs, "diagnostics file:///quiet.txt").await);
+
+        let (mut service, logs) = start(json!({ "maxLineLength": 5 })).await;
+        open(&mut service, "file:///a.txt", 1, "short").await;
+        change(&mut service, "file:///a.txt", 2, "too long").await;
+        assert!(logged(&logs, "diagnostics file:///a.txt: 1").await);
+        change(&mut service, "file:///a.txt", 3, "short").await;
+        assert!(logged(&logs, "diagnostics file:///a.txt: 0").await);
+        change(&mut service, "file:///a.txt", 4, "tiny").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let cleared = logs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.ends_with(": 0"))
+            .count();
+        assert_eq!(cleared, 1);
+    }
+
+    #[tokio::test]
+    async fn changes_only_schedule_diagnostics_when_they_are_configured() {
+        let (mut service, _) = start(json!({ "diagnosticsDebounce": 1000 })).await;
+        open(&mut service, "file:///a.txt", 1, "text").await;
+        change(&mut service, "file:///a.txt", 2, "more text").await;
+        assert!(service.inner().diagnostic_timers.lock().unwrap().is_empty());
+
+        let options = json!({ "diagnosticsDebounce": 1000, "maxLineLength": 5 });
+        let (mut service, _) = start(options).await;
+        open(&mut service, "file:///a.txt", 1, "text").await;
+        change(&mut service, "file:///a.txt", 2, "more text").await;
+        assert_eq!(service.inner().diagnostic_timers.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stopping_ends_background_tasks() {
+        let options = json!({ "idleTimeout": 1, "maxLineLength": 5, "diagnosticsDebounce": 200 });
+        let (mut service, logs) = start(options).await;
+        open(&mut service, "file:///closed.txt", 1, "text").await;
+        let params = json!({ "textDocument": { "uri": "file:///closed.txt" } });
+        send(&mut service, Request::build("textDocument/didClose").params(params).finish()).await;
+        open(&mut service, "file:///a.txt", 1, "short").await;
+        change(&mut service, "file:///a.txt", 2, "too long").await;
+        assert!(!service.inner().tasks.lock().unwrap().is_empty());
+        assert_eq!(service.inner().diagnostic_timers.lock().unwrap().len(), 1);
+
+        service.inner().stop().await;
+        assert!(service.inner().tasks.lock().unwrap().is_empty());
+        assert!(service.inner().diagnostic_timers.lock().unwrap().is_empty());
+
+        // Past both the debounce delay and the idle timeout
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let closed = Url::parse("file:///closed.txt").unwrap();
+        assert!(service.inner().documents.lock().unwrap().contains_key(&closed));
+        let logs = logs.lock().unwrap();
+        assert!(!logs.iter().any(|message| message == "idle, caches trimmed"));
+        assert!(!logs.iter().any(|message| message == "diagnostics file:///a.txt: 1"));
+    }
+
+    #[tokio::test]
+    async fn completions_share_their_edit_range_with_supporting_clients() {
+        let capabilities = json!({
+            "textDocument": {
+                "completion": { "completionList": { "itemDefaults": ["editRange"] } },
+            },
+        });
+        let (mut service, _) = start_with(capabilities, json!({})).await;
+        open(&mut service, "file:///a.txt", 1, "hello help\nhe").await;
+        let mut service = BackendService::new(service);
+
+        let params = json!({
+            "textDocument": { "uri": "file:///a.txt" },
+            "position": { "line": 1, "character": 2 },
+        });
+        let request = Request::build("textDocument/completion")
+            .params(params)
+            .id(2)
+            .finish();
+        let response = service.ready().await.unwrap().call(request).await.unwrap().unwrap();
+        let list = response.result().unwrap();
+
+        let prefix = Range::new(Position::new(1, 0), Position::new(1, 2));
+        assert_eq!(list["itemDefaults"]["editRange"], serde_json::to_value(prefix).unwrap());
+        for item in list["items"].as_array().unwrap() {
+            assert!(item.get("textEdit").is_none() && item.get("textEditText").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn words_are_completed_after_a_slash_matching_no_path() {
+        let root = std::env::temp_dir().join(format!("buffer-ls-slash-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        let (mut service, _) = start(json!({ "pathCompletion": true })).await;
+        let folder = WorkspaceFolder {
+            uri: Url::from_directory_path(&root).unwrap(),
+            name: "root".into(),
+        };
+        let params = json!({ "event": { "added": [folder], "removed": [] } });
+        send(
+            &mut service,
+            Request::build("workspace/didChangeWorkspaceFolders")
+                .params(params)
+                .finish(),
+        )
+        .await;
+        let uri = Url::from_file_path(root.join("notes.txt")).unwrap();
+        open(&mut service, uri.as_str(), 1, "andor\nand/an\nsee ./d").await;
+
+        let labels = complete_at(&mut service, uri.as_str(), Position::new(1, 6)).await;
+        assert!(labels.contains(&"andor".to_string()), "{labels:?}");
+        let labels = complete_at(&mut service, uri.as_str(), Position::new(2, 7)).await;
+        assert_eq!(labels, ["./docs/"]);
+    }
+
+    #[tokio::test]
+    async fn folder_settings_apply_only_to_their_folder() {
+        let options = json!({ "folderSettings": { "one": { "minOccurrences": 2 } } });
+        let (mut service, _) = start(options).await;
+        let folders: Vec<_> = ["one", "two"]
+            .map(|name| WorkspaceFolder {
+                uri: Url::parse(&format!("file:///{name}/")).unwrap(),
+                name: name.into(),
+            })
+            .into();
+        let params = json!({ "event": { "added": folders, "removed": [] } });
+        send(
+            &mut service,
+            Request::build("workspace/didChangeWorkspaceFolders")
+                .params(params)
+                .finish(),
+        )
+        .await;
+        open(&mut service, "file:///one/a.txt", 1, "alpha alpha alto\nal").await;
+        open(&mut service, "file:///two/a.txt", 1, "beta beta bet\nbe").await;
+
+        let labels = complete_at(&mut service, "file:///one/a.txt", Position::new(1, 2)).await;
+        assert_eq!(labels, ["alpha"]);
+        let labels = complete_at(&mut service, "file:///two/a.txt", Position::new(1, 2)).await;
+        assert!(labels.contains(&"bet".to_string()), "{labels:?}");
+    }
+
+    #[test]
+    fn positions_are_counted_in_the_given_encoding() {
+        // é is 2 bytes and 1 UTF-16 unit, 😀 4 bytes and 2 units
+        let text = "abc\néa😀b\n";
+        let b = text.rfind('b').unwrap();
+
+        for (encoding, character) in [(PositionEncoding::Utf8, 7), (PositionEncoding::Utf16, 4)] {
+            let position = Position::new(1, character);
+            assert_eq!(get_char_index_from_position(text, position, encoding), b);
+            assert_eq!(get_position_from_char_index(text, b, encoding), position);
+        }
+        assert_eq!(
+            get_position_from_char_index(text, text.len(), PositionEncoding::Utf8),
+            Position::new(2, 0)
+        );
+    }
+
+    #[test]
+    fn positions_inside_a_character_resolve_to_its_start() {
+        let text = "é😀";
+
+        // The second byte of é, and the second UTF-16 unit of 😀
+        let position = Position::new(0, 1);
+        assert_eq!(get_char_index_from_position(text, position, PositionEncoding::Utf8), 0);
+        let position = Position::new(0, 2);
+        assert_eq!(get_char_index_from_position(text, position, PositionEncoding::Utf16), 2);
+        // Past the end of the line
+        let position = Position::new(0, 99);
+        assert_eq!(get_char_index_from_position(text, position, PositionEncoding::Utf8), 6);
+    }
+
+    #[tokio::test]
+    async fn stale_or_corrupt_snapshots_are_rebuilt() {
+        let text = "#tag foo tag foo";
+        let uri = Url::parse("file:///a.txt").unwrap();
+        let default = TokenizerConfig::default();
+        let path =
+            std::env::temp_dir().join(format!("buffer-ls-stale-{}.json", std::process::id()));
+        let options = json!({ "indexSnapshotPath": path });
+        let rebuilt = OccurrenceIndex::new(text, &CategoryTokenizer, &default, false, None);
+        let indexed = |service: &LspService<Backend>| {
+            let documents = service.inner().documents.lock().unwrap();
+            documents[&uri].occurrences.as_ref().unwrap().offsets().clone()
+        };
+
+        // Taken with other tokenizer settings
+        let sigils = TokenizerConfig { sigil_tokens: true, ..default.clone() };
+        let mut snapshot = Snapshot::new(sigils.clone());
+        let index = OccurrenceIndex::new(text, &CategoryTokenizer, &sigils, false, None);
+        snapshot.insert(uri.clone(), text, &index);
+        snapshot.save(&path).unwrap();
+        let (mut service, logs) = start(options.clone()).await;
+        assert!(logged(&logs, "read the index of 0 documents").await);
+        open(&mut service, uri.as_str(), 1, text).await;
+        assert_eq!(&indexed(&service), rebuilt.offsets());
+
+        // Cut short
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+        let (mut service, logs) = start(options).await;
+        assert!(logged(&logs, "failed to read index snapshot").await);
+        open(&mut service, uri.as_str(), 1, text).await;
+        assert_eq!(&indexed(&service), rebuilt.offsets());
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionList, CompletionTextEdit, Position, Range,
+    TextEdit,
+};
+
+use crate::{casing::collate, character_len, config::Collation};
+
+/// Maximum number of paths offered per request.
+const PATH_COMPLETION_LIMIT: usize = 100;
+
+/// How long a directory listing is reused before the directory is read again.
+const DIRECTORY_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Recent directory listings, so typing a path does not read the same directory on every key.
+#[derive(Debug, Default)]
+pub struct DirectoryCache {
+    /// Names of the entries of each directory, with whether they are directories themselves.
+    listings: HashMap<PathBuf, (Instant, Vec<(String, bool)>)>,
+}
+
+impl DirectoryCache {
+    fn list(&mut self, dir: &Path) -> &[(String, bool)] {
+        self.listings
+            .retain(|_, (read, _)| read.elapsed() < DIRECTORY_CACHE_TTL);
+
+        let (_, entries) = self.listings.entry(dir.to_path_buf()).or_insert_with(|| {
+            let mut entries = std::fs::read_dir(dir)
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| {
+                    let is_dir = entry.file_type().ok()?.is_dir();
+                    Some((entry.file_name().into_string().ok()?, is_dir))
+                })
+                .collect::<Vec<_>>();
+            entries.sort();
+            (Instant::now(), entries)
+        });
+
+        entries
+    }
+}
+
+/// The path being typed at the end of `line_before_cursor`: the text after the last whitespace,
+/// quote or opening bracket, if it contains a `/`.
+fn path_fragment(line_before_cursor: &str) -> Option<&str> {
+    let start = line_before_cursor
+        .rfind(|ch: char| ch.is_whitespace() || matches!(ch, '"' | '\'' | '`' | '(' | '<'))
+        .map_or(0, |i| i + line_before_cursor[i..].chars().next().map_or(1, char::len_utf8));
+    let fragment = &line_before_cursor[start..];
+
+    fragment.contains('/').then_some(fragment)
+}
+
+/// The directory `dir` names under `root`, or `None` when its `..` segments leave `root`.
+///
+/// Resolved lexically, without touching the file system.
+fn resolve(root: &Path, dir: &str) -> Option<PathBuf> {
+    let mut segments = Vec::new();
+    for segment in dir.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    Some(
+        segments
+            .into_iter()
+            .fold(root.to_path_buf(), |path, segment| path.join(segment)),
+    )
+}
+
+/// Complete the path at the end of `line_before_cursor`, typed before `position`, with the paths
+/// under `root` it is a prefix of. `line_after_cursor` is the rest of the line.
+///
+/// Returns `None` when no path matches, as after `and/or` or `1/2`, so the words are completed
+/// instead, and for paths reaching outside of `root`.
+///
+/// Paths are relative to `root`, even with a leading `/` as in root-relative links. When the path
+/// follows a quote or bracket that is not closed later on the line, completing a file also
+/// closes it, with an additional edit when the cursor is in the middle of the path.
+///
+/// With `commit_on_slash`, directories are inserted without their trailing `/` and typing `/`
+/// accepts them, so the next segment can be typed right away. Entries are sorted by `collation`.
+pub fn complete_path(
+    root: &Path,
+    line_before_cursor: &str,
+    line_after_cursor: &str,
+    position: Position,
+    commit_on_slash: bool,
+    collation: Collation,
+    cache: &mut DirectoryCache,
+) -> Option<CompletionList> {
+    let fragment = path_fragment(line_before_cursor)?;
+    let (dir, name) = fragment.rsplit_once('/').unwrap_or(("", fragment));
+    let range = Range::new(
+        Position::new(position.line, position.character.saturating_sub(character_len(fragment))),
+        position,
+    );
+    let opener = line_before_cursor[..line_before_cursor.len() - fragment.len()]
+        .chars()
+        .next_back();
+    let closer = match opener {
+        Some(quote @ ('"' | '\'' | '`')) => Some(quote),
+        Some('(') => Some(')'),
+        Some('<') => Some('>'),
+        _ => None,
+    }
+    .filter(|&closer| !line_after_cursor.contains(closer));
+    let entries = cache.list(&resolve(root, dir)?);
+
+    let mut matching = entries
+        .iter()
+        .filter(|(entry, _)| {
+            entry.starts_with(name) && (!entry.starts_with('.') || name.starts_with('.'))
+        })
+        .collect::<Vec<_>>();
+    // Listings are cached in code point order
+    if collation != Collation::Codepoint {
+        matching.sort_by(|(a, _), (b, _)| collate(a, b, collation));
+    }
+    let items = matching
+        .into_iter()
+        .take(PATH_COMPLETION_LIMIT)
+        .map(|(entry, is_dir)| {
+            let path = format!("{dir}/{entry}{}", if *is_dir { "/" } else { "" });
+            let mut new_text = path.clone();
+            let mut additional_text_edits = None;
+            let commit_characters = (*is_dir && commit_on_slash).then(|| {
+                new_text.pop();
+                vec!["/".to_string()]
+            });
+
+            if let Some(closer) = closer.filter(|_| !is_dir) {
+                // Close after the rest of the path, if the cursor is in the middle of it. Edits
+                // elsewhere must not touch the main one, so otherwise the main one closes it.
+                let rest = line_after_cursor
+                    .split(|ch: char| ch.is_whitespace())
+                    .next()
+                    .unwrap_or_default();
+                if rest.is_empty() {
+                    new_text.push(closer);
+                } else {
+                    let end =
+                        Position::new(position.line, position.character + character_len(rest));
+                    additional_text_edits =
+                        Some(vec![TextEdit::new(Range::new(end, end), closer.to_string())]);
+                }
+            }
+
+            CompletionItem {
+                label: path,
+                kind: Some(if *is_dir {
+                    CompletionItemKind::FOLDER
+                } else {
+                    CompletionItemKind::FILE
+                }),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, new_text))),
+                additional_text_edits,
+                commit_characters,
+                ..CompletionItem::default()
+            }
+        })
+        .collect::<Vec<_>>();
+    if items.is_empty() {
+        return None;
+    }
+
+    Some(CompletionList {
+        is_incomplete: items.len() == PATH_COMPLETION_LIMIT,
+        items,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory holding `docs/guide.md` and `src/`, named after `test`.
+    fn workspace(test: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("buffer-ls-path-{test}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        std::fs::write(root.join("docs/guide.md"), "").unwrap();
+        root
+    }
+
+    fn labels(root: &Path, line: &str) -> Option<Vec<String>> {
+        let position = Position::new(0, character_len(line));
+        let list = complete_path(
+            root,
+            line,
+            "",
+            position,
+            false,
+            Collation::Codepoint,
+            &mut DirectoryCache::default(),
+        )?;
+
+        Some(list.items.into_iter().map(|item| item.label).collect())
+    }
+
+    #[test]
+    fn paths_under_the_root_are_completed() {
+        let root = workspace("under");
+
+        assert_eq!(labels(&root, "see ./d").unwrap(), ["./docs/"]);
+        assert_eq!(labels(&root, "see /docs/g").unwrap(), ["/docs/guide.md"]);
+        assert_eq!(labels(&root, "see docs/../s").unwrap(), ["docs/../src/"]);
+    }
+
+    #[test]
+    fn fragments_matching_no_path_are_left_to_word_completion() {
+        let root = workspace("no-match");
+
+        assert_eq!(labels(&root, "this and/o"), None);
+        assert_eq!(labels(&root, "1/"), None);
+        assert_eq!(labels(&root, "see https://example.com/pa"), None);
+        assert_eq!(labels(&root, "see ./missing/"), None);
+    }
+
+    #[test]
+    fn paths_outside_the_root_are_not_completed() {
+        let root = workspace("outside");
+        let name = root.file_name().unwrap().to_str().unwrap().to_string();
+
+        assert_eq!(labels(&root, "see ../"), None);
+        assert_eq!(labels(&root, "see docs/../../"), None);
+        assert_eq!(labels(&root, &format!("see ../{name}/d")), None);
+        assert_eq!(resolve(&root, "/docs/..//./src"), Some(root.join("src")));
+    }
+}
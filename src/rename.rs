@@ -0,0 +1,64 @@
+use tower_lsp::lsp_types::{Position, Range, TextEdit};
+
+use crate::{
+    character_len, config::Config, document::Document, find_word_at_position,
+    get_positions_from_char_indices, tokenizer::Tokenizer, PositionEncoding,
+};
+
+/// Compute the edits renaming every occurrence of the word at `position` in `document`.
+///
+/// The edits are sorted by position and never overlap, as every occurrence is a distinct token,
+/// so clients can apply them as a single atomic (and single undo step) change.
+///
+/// Returns `None` when [`Config::lightweight`] is enabled, as occurrences are not indexed then.
+pub fn rename(
+    document: &Document,
+    position: Position,
+    new_name: &str,
+    tokenizer: &dyn Tokenizer,
+    config: &Config,
+) -> Option<Vec<TextEdit>> {
+    let text = &document.text;
+    let (_, word) = find_word_at_position(text, position, tokenizer, &config.tokenizer)?;
+    let len = character_len(word);
+    let offsets = document.offsets_of(word, tokenizer, &config.tokenizer)?;
+
+    let edits = get_positions_from_char_indices(text, &offsets, PositionEncoding::Utf16)
+        .into_iter()
+        .map(|start| {
+            let end = Position::new(start.line, start.character + len);
+            TextEdit::new(Range::new(start, end), new_name.to_string())
+        })
+        .collect();
+
+    Some(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::CategoryTokenizer;
+
+    #[test]
+    fn renames_every_occurrence_on_a_line_in_order() {
+        let config = Config::default();
+        let document = Document::new(
+            "abc x abc".to_string(),
+            1,
+            "plaintext".to_string(),
+            true,
+            &CategoryTokenizer,
+            &config,
+        );
+
+        let edits = rename(&document, Position::new(0, 1), "de", &CategoryTokenizer, &config);
+
+        assert_eq!(
+            edits,
+            Some(vec![
+                TextEdit::new(Range::new(Position::new(0, 0), Position::new(0, 3)), "de".into()),
+                TextEdit::new(Range::new(Position::new(0, 6), Position::new(0, 9)), "de".into()),
+            ])
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use std::{collections::HashMap, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::Url;
+
+use crate::{
+    config::TokenizerConfig,
+    index::{OccurrenceIndex, Occurrences},
+};
+
+/// Version of the snapshot format, also bumped when the same settings split text differently.
+/// Snapshots of other versions are ignored.
+const SNAPSHOT_VERSION: u32 = 4;
+
+/// The indices of the documents open when the server last shut down, saved to
+/// [`Config::index_snapshot_path`](crate::config::Config::index_snapshot_path) so reopening them
+/// does not split their text again.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Snapshot {
+    version: u32,
+    /// Settings the indices were built with. Indices built with other settings are stale.
+    tokenizer: TokenizerConfig,
+    documents: HashMap<Url, SnapshotEntry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SnapshotEntry {
+    /// [`content_hash`] of the indexed text.
+    hash: u64,
+    offsets: HashMap<String, Occurrences>,
+}
+
+impl Snapshot {
+    pub fn new(tokenizer: TokenizerConfig) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            tokenizer,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// Read the snapshot at `path`. A snapshot of another version, or taken with other tokenizer
+    /// settings, is read as empty.
+    pub fn load(path: &Path, tokenizer: &TokenizerConfig) -> io::Result<Self> {
+        let snapshot: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        if snapshot.version != SNAPSHOT_VERSION || snapshot.tokenizer != *tokenizer {
+            return Ok(Self::new(tokenizer.clone()));
+        }
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot to `path`, through a temporary file renamed over it so a crash while
+    /// writing leaves the previous snapshot rather than a truncated one.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        std::fs::write(&temporary, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temporary, path)
+    }
+
+    /// Take the saved occurrences of `uri`, if its text is still `text` and it would still be
+    /// split with the same `tokenizer` settings. Occurrences that were only counted, but would be
+    /// tracked with `max_tracked`, make the saved ones stale too.
+    pub fn take(
+        &mut self,
+        uri: &Url,
+        text: &str,
+        tokenizer: &TokenizerConfig,
+        max_tracked: Option<usize>,
+    ) -> Option<HashMap<String, Occurrences>> {
+        let entry = self.documents.remove(uri)?;
+        let now_tracked = entry.offsets.values().any(|occurrences| {
+            matches!(*occurrences, Occurrences::Untracked(count)
+                if max_tracked.is_none_or(|max| count <= max))
+        });
+
+        (entry.hash == content_hash(text) && self.tokenizer == *tokenizer && !now_tracked)
+            .then_some(entry.offsets)
+    }
+
+    /// Save the occurrences of `uri`, whose text is `text`.
+    pub fn insert(&mut self, uri: Url, text: &str, occurrences: &OccurrenceIndex) {
+        self.documents.insert(
+            uri,
+            SnapshotEntry {
+                hash: content_hash(text),
+                offsets: occurrences.offsets().clone(),
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+}
+
+/// FNV-1a hash of `text` and its length. Unlike the standard library hasher, it is stable across
+/// builds, so hashes in snapshots stay comparable.
+fn content_hash(text: &str) -> u64 {
+    text.bytes()
+        .chain((text.len() as u64).to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tokenizer::CategoryTokenizer;
+
+    const TEXT: &str = "#tag foo tag foo";
+
+    fn uri() -> Url {
+        Url::parse("file:///a.txt").unwrap()
+    }
+
+    /// A path for the snapshot of `test`, with nothing there yet.
+    fn snapshot_path(test: &str) -> PathBuf {
+        let path = std::env::temp_dir()
+            .join(format!("buffer-ls-snapshot-{test}-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// A snapshot of [`TEXT`], saved at `path`, and the occurrences saved in it.
+    fn save(path: &Path, tokenizer: &TokenizerConfig) -> HashMap<String, Occurrences> {
+        let index = OccurrenceIndex::new(TEXT, &CategoryTokenizer, tokenizer, false, None);
+        let mut snapshot = Snapshot::new(tokenizer.clone());
+        snapshot.insert(uri(), TEXT, &index);
+        snapshot.save(path).unwrap();
+
+        index.offsets().clone()
+    }
+
+    #[test]
+    fn saved_occurrences_are_read_back() {
+        let path = snapshot_path("round-trip");
+        let tokenizer = TokenizerConfig::default();
+        let saved = save(&path, &tokenizer);
+
+        let mut snapshot = Snapshot::load(&path, &tokenizer).unwrap();
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.take(&uri(), TEXT, &tokenizer, None), Some(saved));
+        // Taken once
+        assert_eq!(snapshot.take(&uri(), TEXT, &tokenizer, None), None);
+    }
+
+    #[test]
+    fn occurrences_of_another_text_are_stale() {
+        let path = snapshot_path("other-text");
+        let tokenizer = TokenizerConfig::default();
+        save(&path, &tokenizer);
+
+        let mut snapshot = Snapshot::load(&path, &tokenizer).unwrap();
+        assert_eq!(snapshot.take(&uri(), "#tag foo tag fob", &tokenizer, None), None);
+    }
+
+    #[test]
+    fn snapshots_taken_with_other_tokenizer_settings_are_stale() {
+        let path = snapshot_path("other-settings");
+        save(&path, &TokenizerConfig::default());
+        let tokenizer = TokenizerConfig { sigil_tokens: true, ..TokenizerConfig::default() };
+
+        let mut snapshot = Snapshot::load(&path, &tokenizer).unwrap();
+        assert_eq!(snapshot.len(), 0);
+        assert_eq!(snapshot.take(&uri(), TEXT, &tokenizer, None), None);
+    }
+
+    #[test]
+    fn corrupt_snapshots_fail_to_load() {
+        let path = snapshot_path("corrupt");
+        let tokenizer = TokenizerConfig::default();
+        save(&path, &tokenizer);
+        let saved = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+
+        let err = Snapshot::load(&path, &tokenizer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn saving_replaces_the_previous_snapshot_whole() {
+        let path = snapshot_path("replace");
+        let tokenizer = TokenizerConfig::default();
+        save(&path, &tokenizer);
+        Snapshot::new(tokenizer.clone()).save(&path).unwrap();
+
+        assert_eq!(Snapshot::load(&path, &tokenizer).unwrap().len(), 0);
+        let mut temporary = path.into_os_string();
+        temporary.push(".tmp");
+        assert!(!Path::new(&temporary).exists());
+    }
+}
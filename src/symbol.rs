@@ -0,0 +1,123 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tower_lsp::lsp_types::{Location, Position, Range, SymbolInformation, SymbolKind, Url};
+
+use crate::{
+    character_len,
+    completion::match_candidate,
+    config::{Config, Matching},
+    document::Document,
+    split_positions,
+    text::{categorize_char_with, CharCategory},
+    tokenizer::Tokenizer,
+};
+
+/// Maximum number of symbols returned. Unlike completion lists, which the client asks for again
+/// as the user types, this is not tied to `maxCompletionItems`.
+const WORKSPACE_SYMBOL_LIMIT: usize = 1000;
+
+/// The unique words of `documents` matching `query`, most frequent first, each located at its
+/// first occurrence, up to [`WORKSPACE_SYMBOL_LIMIT`] of them.
+///
+/// Each document is split and matched with its settings in `configs`, which has those of every
+/// open document. Words are matched like completion candidates, ignoring case under the
+/// configured folding, except that [`Matching::None`] is treated as [`Matching::Prefix`]: unlike
+/// completion, the query is all a client has to filter on.
+pub fn workspace_symbols(
+    query: &str,
+    documents: &HashMap<Url, Document>,
+    configs: &HashMap<&Url, Arc<Config>>,
+    tokenizer: &dyn Tokenizer,
+) -> Vec<SymbolInformation> {
+    let mut uris = documents
+        .iter()
+        .filter(|(_, document)| document.open && document.indexed)
+        .map(|(uri, _)| uri)
+        .collect::<Vec<_>>();
+    // So the first occurrence does not depend on the order of the map
+    uris.sort();
+
+    let mut symbols = Vec::<(SymbolInformation, usize)>::new();
+    let mut index = HashMap::<&str, usize>::new();
+
+    for uri in uris {
+        let text = &documents[uri].text;
+        let config = &configs[uri];
+        let matching = match config.matching {
+            Matching::None => Matching::Prefix,
+            matching => matching,
+        };
+
+        for (start, word) in split_positions(text, tokenizer, &config.tokenizer) {
+            let is_word = word.chars().last().is_some_and(|ch| {
+                categorize_char_with(ch, &config.tokenizer) == CharCategory::Word
+            });
+            let matched = match_candidate(word, query, matching, Some(config.case_folding));
+            if !is_word || matched.is_none() {
+                continue;
+            }
+
+            match index.get(word) {
+                Some(&i) => symbols[i].1 += 1,
+                None => {
+                    let end = Position::new(start.line, start.character + character_len(word));
+
+                    index.insert(word, symbols.len());
+                    #[allow(deprecated)]
+                    symbols.push((
+                        SymbolInformation {
+                            name: word.to_string(),
+                            kind: SymbolKind::STRING,
+                            tags: None,
+                            deprecated: None,
+                            location: Location::new(uri.clone(), Range::new(start, end)),
+                            container_name: None,
+                        },
+                        1,
+                    ));
+                }
+            }
+        }
+    }
+
+    // Stable, so equally frequent words stay in order of first occurrence
+    symbols.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    symbols.truncate(WORKSPACE_SYMBOL_LIMIT);
+
+    symbols.into_iter().map(|(symbol, _)| symbol).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::CategoryTokenizer;
+
+    fn document(text: &str, config: &Config) -> Document {
+        Document::new(
+            text.to_string(),
+            0,
+            "plaintext".to_string(),
+            true,
+            &CategoryTokenizer,
+            config,
+        )
+    }
+
+    #[test]
+    fn symbols_match_ignoring_case_with_the_settings_of_their_document() {
+        let (a, b) =
+            (Url::parse("file:///a/x.txt").unwrap(), Url::parse("file:///b/x.txt").unwrap());
+        let mut underscores = Config::default();
+        underscores.tokenizer.underscore_separator = true;
+        let documents = HashMap::from([
+            (a.clone(), document("Foo_bar food", &Config::default())),
+            (b.clone(), document("FOO_BAR", &underscores)),
+        ]);
+        let configs = HashMap::from([(&a, Arc::default()), (&b, Arc::new(underscores))]);
+
+        let symbols = workspace_symbols("foo", &documents, &configs, &CategoryTokenizer);
+        let names = symbols.iter().map(|symbol| symbol.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Foo_bar", "food", "FOO"]);
+        assert_eq!(symbols[2].location.uri, b);
+    }
+}
@@ -0,0 +1,522 @@
+//! Character categorization and tokenization, as used by the server to split buffers into
+//! words.
+//!
+//! These functions are the exact primitives the server tokenizes with, exposed so crates
+//! embedding the engine split text the same way. They follow semantic versioning: the
+//! categories a character falls in and the tokens a text splits into only change in a minor
+//! release, and are listed in the changelog when they do. [`CharCategory`] may gain variants in
+//! a minor release, so matches on it need a wildcard arm.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CjkCompletion, TokenizerConfig};
+
+/// The category of a character. Text is split into tokens wherever the category changes.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum CharCategory {
+    /// Spaces and tabs, but not line endings.
+    Whitespace,
+    /// Line endings, including the Unicode line and paragraph separators.
+    Eol,
+    /// Letters, digits and `_`.
+    Word,
+    /// Punctuation, and math, currency and modifier symbols.
+    Punctuation,
+    /// Anything else, such as emoji or control characters.
+    Unknown,
+    Hiragana,
+    Katakana,
+    /// CJK unified and compatibility ideographs.
+    Kanji,
+}
+
+/// Split `s` into consecutive tokens covering all of it, including the whitespace and punctuation
+/// between words.
+///
+/// Tokens are in logical order, the order characters are typed and stored in, whatever the
+/// direction of their script. Where letters of different scripts meet, such as Latin and
+/// Cyrillic or Hebrew, a new word starts, as a single word never mixes scripts. Digits and
+/// letters of scripts not told apart join the word they are in.
+///
+/// ```
+/// use buffer_language_server::{config::TokenizerConfig, text::split};
+///
+/// let config = TokenizerConfig::default();
+/// assert_eq!(split("שלום עולם", &config), ["שלום", " ", "עולם"]);
+/// assert_eq!(split("كلمة٢ word", &config), ["كلمة٢", " ", "word"]);
+/// assert_eq!(split("wordשלוםكلمة", &config), ["word", "שלום", "كلمة"]);
+/// assert_eq!(split("словоword2", &config), ["слово", "word2"]);
+/// ```
+pub fn split<'a>(s: &'a str, config: &TokenizerConfig) -> Vec<&'a str> {
+    let mut result = Vec::new();
+    split_with(s, config, |_, token| result.push(token));
+    result
+}
+
+/// Like [`split`], but only keeps words, with their byte offset. Indexing goes through this, so
+/// the whitespace and punctuation between words are never collected.
+pub fn split_words<'a>(s: &'a str, config: &TokenizerConfig) -> Vec<(usize, &'a str)> {
+    let mut result = Vec::new();
+    split_with(s, config, |start, token| {
+        if is_word(token, config) {
+            result.push((start, token));
+        }
+    });
+    result
+}
+
+/// Whether `token` is a word rather than whitespace or punctuation, as kana, ideographs and
+/// emoji are too since they are completed. Tokens are classified by their last character, so
+/// sigil tokens like `#project` are words.
+pub fn is_word(token: &str, config: &TokenizerConfig) -> bool {
+    token.chars().next_back().is_some_and(|ch| {
+        !matches!(
+            categorize_char_with(ch, config),
+            CharCategory::Whitespace | CharCategory::Eol | CharCategory::Punctuation
+        )
+    })
+}
+
+/// Whether a token boundary lies at byte offset `index` of `s`, that is whether [`split`] starts
+/// or ends a token there. The start and end of `s` are boundaries.
+///
+/// Boundaries depend on the surrounding characters, not only on the two around `index`: a sigil
+/// joins the word after it, for instance. Only the characters since the last whitespace before
+/// `index` and the two after it are looked at, so calls stay cheap on long texts.
+pub fn is_boundary(s: &str, index: usize, config: &TokenizerConfig) -> bool {
+    if index == 0 || index == s.len() {
+        return true;
+    }
+    if !s.is_char_boundary(index) {
+        return false;
+    }
+
+    // Splitting continues the same way after any whitespace, whatever came before it
+    let start = s[..index]
+        .char_indices()
+        .rev()
+        .find(|&(_, ch)| is_separator(ch, config))
+        .map_or(0, |(start, _)| start);
+    // Whether a token ends before a character depends on that character and the next one
+    let end = s[index..]
+        .char_indices()
+        .nth(2)
+        .map_or(s.len(), |(end, _)| index + end);
+
+    let mut boundary = false;
+    split_with(&s[start..end], config, |token_start, _| {
+        boundary |= start + token_start == index;
+    });
+    boundary
+}
+
+/// Whether `ch` always splits as whitespace, never joining the token before or after it.
+fn is_separator(ch: char, config: &TokenizerConfig) -> bool {
+    match config.separators.get(&ch) {
+        Some(&separator) => separator,
+        None => {
+            let joins = config.joiners && char_is_joiner(ch)
+                || config.sigil_tokens && config.sigils.contains(ch);
+            !joins
+                && matches!(
+                    categorize_char_with(ch, config),
+                    CharCategory::Whitespace | CharCategory::Eol
+                )
+        }
+    }
+}
+
+/// Call `emit` with the byte offset and text of every token of `s`, in order.
+fn split_with<'a>(s: &'a str, config: &TokenizerConfig, mut emit: impl FnMut(usize, &'a str)) {
+    let mut word_start = 0;
+    let mut last_category = None;
+    let mut previous_char_category = None;
+    // Whether the last letter or digit of the current word was a digit
+    let mut last_digit = None;
+    // Script of the last letter of the current word
+    let mut last_script = None;
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((i, ch)) = chars.next() {
+        let char_category = match config.separators.get(&ch) {
+            Some(true) => CharCategory::Whitespace,
+            // Not a separator: the character continues whichever token precedes it
+            Some(false) => last_category.unwrap_or(CharCategory::Unknown),
+            None if config.joiners && char_is_joiner(ch) => {
+                last_category.unwrap_or(CharCategory::Unknown)
+            }
+            None => categorize_char_with(ch, config),
+        };
+        let mut current_category = char_category;
+
+        if config.sigil_tokens
+            && config.sigils.contains(ch)
+            && previous_char_category != Some(CharCategory::Word)
+            && chars
+                .peek()
+                .is_some_and(|&(_, next)| categorize_char_with(next, config) == CharCategory::Word)
+        {
+            current_category = CharCategory::Word;
+        }
+
+        // An apostrophe between two word characters, as in `don't`, joins them
+        if config.apostrophe_words
+            && matches!(ch, '\'' | '\u{2019}')
+            && previous_char_category == Some(CharCategory::Word)
+            && chars
+                .peek()
+                .is_some_and(|&(_, next)| categorize_char_with(next, config) == CharCategory::Word)
+        {
+            current_category = CharCategory::Word;
+        }
+
+        let is_cjk = matches!(
+            current_category,
+            CharCategory::Hiragana | CharCategory::Katakana | CharCategory::Kanji
+        );
+        let split_cjk = is_cjk && config.cjk_completion == CjkCompletion::Character;
+
+        // Joiners, sigils and apostrophes go with letters and digits alike
+        let digit = (config.split_digits
+            && char_category == CharCategory::Word
+            && categorize_char_with(ch, config) == CharCategory::Word)
+            .then(|| ch.is_numeric());
+        let split_digits = digit.is_some_and(|digit| last_digit.is_some_and(|last| last != digit));
+        let script = (char_category == CharCategory::Word).then(|| script(ch)).flatten();
+        let split_scripts = current_category == CharCategory::Word
+            && script.is_some_and(|script| last_script.is_some_and(|last| last != script));
+
+        if last_category.is_some_and(|last| {
+            last != current_category || split_cjk || split_digits || split_scripts
+        }) {
+            emit(word_start, &s[word_start..i]);
+            word_start = i;
+        }
+
+        if current_category != CharCategory::Word {
+            last_digit = None;
+            last_script = None;
+        } else {
+            last_digit = digit.or(last_digit);
+            last_script = script.or(last_script);
+        }
+
+        last_category = Some(current_category);
+        previous_char_category = Some(char_category);
+    }
+
+    if word_start < s.len() {
+        emit(word_start, &s[word_start..]);
+    }
+}
+
+/// Like [`categorize_char`], but `_` is punctuation when
+/// [`TokenizerConfig::underscore_separator`] is enabled.
+#[inline]
+pub fn categorize_char_with(ch: char, config: &TokenizerConfig) -> CharCategory {
+    if ch == '_' && config.underscore_separator {
+        CharCategory::Punctuation
+    } else {
+        categorize_char(ch)
+    }
+}
+
+/// The category of `ch` with the default settings.
+///
+/// ```
+/// use buffer_language_server::text::{categorize_char, CharCategory};
+///
+/// assert_eq!(categorize_char('a'), CharCategory::Word);
+/// assert_eq!(categorize_char('ひ'), CharCategory::Hiragana);
+/// assert_eq!(categorize_char('\n'), CharCategory::Eol);
+/// ```
+pub fn categorize_char(ch: char) -> CharCategory {
+    if char_is_hiragana(ch) {
+        CharCategory::Hiragana
+    } else if char_is_katakana(ch) {
+        CharCategory::Katakana
+    } else if char_is_kanji(ch) {
+        CharCategory::Kanji
+    } else if char_is_line_ending(ch) {
+        CharCategory::Eol
+    } else if char_is_whitespace(ch) {
+        CharCategory::Whitespace
+    } else if char_is_word(ch) {
+        CharCategory::Word
+    } else if char_is_punctuation(ch) {
+        CharCategory::Punctuation
+    } else {
+        CharCategory::Unknown
+    }
+}
+
+/// The scripts whose letters make separate words where they meet, like the kana and kanji
+/// categories do for CJK text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Syriac,
+    Thaana,
+    Nko,
+    Indic,
+    Thai,
+    Lao,
+    Tibetan,
+    Myanmar,
+    Georgian,
+    Hangul,
+    Ethiopic,
+    Khmer,
+    Mongolian,
+}
+
+/// Blocks of the letters of each [`Script`], sorted and not overlapping. Indic scripts are not
+/// told apart, as they are rarely written next to each other.
+const SCRIPT_BLOCKS: &[(char, char, Script)] = &[
+    ('\u{0041}', '\u{024F}', Script::Latin), // Basic Latin to Latin Extended-B: https://www.unicode.org/charts/PDF/U0180.pdf
+    ('\u{0250}', '\u{02AF}', Script::Latin), // IPA Extensions: https://www.unicode.org/charts/PDF/U0250.pdf
+    ('\u{0370}', '\u{03FF}', Script::Greek), // Greek and Coptic: https://www.unicode.org/charts/PDF/U0370.pdf
+    ('\u{0400}', '\u{052F}', Script::Cyrillic), // Cyrillic and Cyrillic Supplement: https://www.unicode.org/charts/PDF/U0400.pdf
+    ('\u{0530}', '\u{058F}', Script::Armenian), // Armenian: https://www.unicode.org/charts/PDF/U0530.pdf
+    ('\u{0590}', '\u{05FF}', Script::Hebrew), // Hebrew: https://www.unicode.org/charts/PDF/U0590.pdf
+    ('\u{0600}', '\u{06FF}', Script::Arabic), // Arabic: https://www.unicode.org/charts/PDF/U0600.pdf
+    ('\u{0700}', '\u{074F}', Script::Syriac), // Syriac: https://www.unicode.org/charts/PDF/U0700.pdf
+    ('\u{0750}', '\u{077F}', Script::Arabic), // Arabic Supplement: https://www.unicode.org/charts/PDF/U0750.pdf
+    ('\u{0780}', '\u{07BF}', Script::Thaana), // Thaana: https://www.unicode.org/charts/PDF/U0780.pdf
+    ('\u{07C0}', '\u{07FF}', Script::Nko),    // NKo: https://www.unicode.org/charts/PDF/U07C0.pdf
+    ('\u{0860}', '\u{086F}', Script::Syriac), // Syriac Supplement: https://www.unicode.org/charts/PDF/U0860.pdf
+    ('\u{0870}', '\u{08FF}', Script::Arabic), // Arabic Extended-B and Extended-A: https://www.unicode.org/charts/PDF/U08A0.pdf
+    ('\u{0900}', '\u{0DFF}', Script::Indic), // Devanagari to Sinhala: https://www.unicode.org/charts/PDF/U0900.pdf
+    ('\u{0E00}', '\u{0E7F}', Script::Thai),  // Thai: https://www.unicode.org/charts/PDF/U0E00.pdf
+    ('\u{0E80}', '\u{0EFF}', Script::Lao),   // Lao: https://www.unicode.org/charts/PDF/U0E80.pdf
+    ('\u{0F00}', '\u{0FFF}', Script::Tibetan), // Tibetan: https://www.unicode.org/charts/PDF/U0F00.pdf
+    ('\u{1000}', '\u{109F}', Script::Myanmar), // Myanmar: https://www.unicode.org/charts/PDF/U1000.pdf
+    ('\u{10A0}', '\u{10FF}', Script::Georgian), // Georgian: https://www.unicode.org/charts/PDF/U10A0.pdf
+    ('\u{1100}', '\u{11FF}', Script::Hangul), // Hangul Jamo: https://www.unicode.org/charts/PDF/U1100.pdf
+    ('\u{1200}', '\u{139F}', Script::Ethiopic), // Ethiopic and Ethiopic Supplement: https://www.unicode.org/charts/PDF/U1200.pdf
+    ('\u{1780}', '\u{17FF}', Script::Khmer), // Khmer: https://www.unicode.org/charts/PDF/U1780.pdf
+    ('\u{1800}', '\u{18AF}', Script::Mongolian), // Mongolian: https://www.unicode.org/charts/PDF/U1800.pdf
+    ('\u{1C80}', '\u{1C8F}', Script::Cyrillic), // Cyrillic Extended-C: https://www.unicode.org/charts/PDF/U1C80.pdf
+    ('\u{1C90}', '\u{1CBF}', Script::Georgian), // Georgian Extended: https://www.unicode.org/charts/PDF/U1C90.pdf
+    ('\u{1E00}', '\u{1EFF}', Script::Latin), // Latin Extended Additional: https://www.unicode.org/charts/PDF/U1E00.pdf
+    ('\u{1F00}', '\u{1FFF}', Script::Greek), // Greek Extended: https://www.unicode.org/charts/PDF/U1F00.pdf
+    ('\u{2C60}', '\u{2C7F}', Script::Latin), // Latin Extended-C: https://www.unicode.org/charts/PDF/U2C60.pdf
+    ('\u{2D00}', '\u{2D2F}', Script::Georgian), // Georgian Supplement: https://www.unicode.org/charts/PDF/U2D00.pdf
+    ('\u{2DE0}', '\u{2DFF}', Script::Cyrillic), // Cyrillic Extended-A: https://www.unicode.org/charts/PDF/U2DE0.pdf
+    ('\u{3130}', '\u{318F}', Script::Hangul), // Hangul Compatibility Jamo: https://www.unicode.org/charts/PDF/U3130.pdf
+    ('\u{A640}', '\u{A69F}', Script::Cyrillic), // Cyrillic Extended-B: https://www.unicode.org/charts/PDF/UA640.pdf
+    ('\u{A720}', '\u{A7FF}', Script::Latin), // Latin Extended-D: https://www.unicode.org/charts/PDF/UA720.pdf
+    ('\u{AB30}', '\u{AB6F}', Script::Latin), // Latin Extended-E: https://www.unicode.org/charts/PDF/UAB30.pdf
+    ('\u{AC00}', '\u{D7FF}', Script::Hangul), // Hangul Syllables and Jamo Extended-B: https://www.unicode.org/charts/PDF/UAC00.pdf
+    ('\u{FB00}', '\u{FB06}', Script::Latin), // Latin ligatures: https://www.unicode.org/charts/PDF/UFB00.pdf
+    ('\u{FB13}', '\u{FB17}', Script::Armenian), // Armenian ligatures: https://www.unicode.org/charts/PDF/UFB00.pdf
+    ('\u{FB1D}', '\u{FB4F}', Script::Hebrew), // Hebrew presentation forms: https://www.unicode.org/charts/PDF/UFB00.pdf
+    ('\u{FB50}', '\u{FDFF}', Script::Arabic), // Arabic Presentation Forms-A: https://www.unicode.org/charts/PDF/UFB50.pdf
+    ('\u{FE70}', '\u{FEFF}', Script::Arabic), // Arabic Presentation Forms-B: https://www.unicode.org/charts/PDF/UFE70.pdf
+    ('\u{FF21}', '\u{FF5A}', Script::Latin), // Fullwidth Latin letters: https://www.unicode.org/charts/PDF/UFF00.pdf
+    ('\u{1EE00}', '\u{1EEFF}', Script::Arabic), // Arabic Mathematical Alphabetic Symbols: https://www.unicode.org/charts/PDF/U1EE00.pdf
+];
+
+/// The script of `ch` if it is a letter of one of [`SCRIPT_BLOCKS`]. Digits, marks and letters
+/// of other scripts have none and join whichever letters surround them.
+#[inline]
+fn script(ch: char) -> Option<Script> {
+    if !ch.is_alphabetic() {
+        return None;
+    }
+    if ch.is_ascii() {
+        return Some(Script::Latin);
+    }
+
+    let block = SCRIPT_BLOCKS.partition_point(|&(_, end, _)| end < ch);
+    SCRIPT_BLOCKS
+        .get(block)
+        .filter(|&&(start, _, _)| start <= ch)
+        .map(|&(_, _, script)| script)
+}
+
+// Determine whether a character is a hiragana character.
+#[inline]
+fn char_is_hiragana(ch: char) -> bool {
+    ('\u{3041}'..='\u{3096}').contains(&ch) || ('\u{3099}'..='\u{309F}').contains(&ch) // Hiragana: https://www.unicode.org/charts/PDF/U3040.pdf
+        || ('\u{1B100}'..='\u{1B12F}').contains(&ch) // Kana Extended-A: https://www.unicode.org/charts/PDF/U1B100.pdf
+        || ('\u{1AFF0}'..='\u{1AFFF}').contains(&ch) // Kana Extended-B: https://www.unicode.org/charts/PDF/U1AFF0.pdf
+        || ('\u{1B000}'..='\u{1B0FF}').contains(&ch) // Kana Supplement: https://www.unicode.org/charts/PDF/U1B000.pdf
+        || ('\u{1B130}'..='\u{1B16F}').contains(&ch) // Small Kana Extension: https://www.unicode.org/charts/PDF/U1B130.pdf
+}
+
+// Determine whether a character is a katakana character.
+#[inline]
+fn char_is_katakana(ch: char) -> bool {
+    ('\u{30A0}'..='\u{30FF}').contains(&ch) // Katakana: https://www.unicode.org/charts/PDF/U30A0.pdf
+}
+
+// Determine whether a character is a kanji, or CJK Unified Ideographs, character.
+#[inline]
+fn char_is_kanji(ch: char) -> bool {
+    ('\u{4E00}'..='\u{9FFF}').contains(&ch) // CJK Unified Ideographs: https://www.unicode.org/charts/PDF/U4E00.pdf
+        || ('\u{3400}'..='\u{4DBF}').contains(&ch) // CJK Unified Ideographs Extension A: https://www.unicode.org/charts/PDF/U3400.pdf
+        || ('\u{20000}'..='\u{2A6DF}').contains(&ch) // CJK Unified Ideographs Extension B: https://www.unicode.org/charts/PDF/U20000.pdf
+        || ('\u{2A700}'..='\u{2B739}').contains(&ch) // CJK Unified Ideographs Extension C: https://www.unicode.org/charts/PDF/U2A700.pdf
+        || ('\u{2B740}'..='\u{2B81D}').contains(&ch) // CJK Unified Ideographs Extension D: https://www.unicode.org/charts/PDF/U2B740.pdf
+        || ('\u{2B820}'..='\u{2CEA1}').contains(&ch) // CJK Unified Ideographs Extension E: https://www.unicode.org/charts/PDF/U2B820.pdf
+        || ('\u{2CEB0}'..='\u{2EBE0}').contains(&ch) // CJK Unified Ideographs Extension F: https://www.unicode.org/charts/PDF/U2CEB0.pdf
+        || ('\u{30000}'..='\u{3134A}').contains(&ch) // CJK Unified Ideographs Extension G: https://www.unicode.org/charts/PDF/U30000.pdf
+        || ('\u{31350}'..='\u{323AF}').contains(&ch) // CJK Unified Ideographs Extension H: https://www.unicode.org/charts/PDF/U31350.pdf
+        || ('\u{2EBF0}'..='\u{2EE5D}').contains(&ch) // CJK Unified Ideographs Extension H: https://www.unicode.org/charts/PDF/U2EBF0.pdf
+        || ('\u{F900}'..='\u{FAFF}').contains(&ch) // CJK Compatibility Ideographs: https://www.unicode.org/charts/PDF/UF900.pdf
+        || ('\u{2F800}'..='\u{2FA1F}').contains(&ch) // CJK Compatibility Ideographs Supplement: https://www.unicode.org/charts/PDF/U2F800.pdf
+}
+
+// Determine whether a character is a line ending.
+#[inline]
+fn char_is_line_ending(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{000A}' | '\u{000B}' | '\u{000C}' | '\u{000D}' | '\u{0085}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+/// Determine whether a character joins the characters around it into a single grapheme or word,
+/// such as the zero width joiner of emoji sequences or the variation selectors.
+#[inline]
+pub fn char_is_joiner(ch: char) -> bool {
+    matches!(
+        ch,
+        '\u{200C}' | // Zero Width Non-joiner, inside words in Persian and Indic scripts
+        '\u{200D}' | // Zero Width Joiner
+        '\u{FE00}'..='\u{FE0F}' | // Variation Selectors
+        '\u{E0100}'..='\u{E01EF}' // Variation Selectors Supplement
+    )
+}
+
+#[inline]
+fn char_is_word(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+#[inline]
+fn char_is_punctuation(ch: char) -> bool {
+    use unicode_general_category::{get_general_category, GeneralCategory};
+
+    matches!(
+        get_general_category(ch),
+        GeneralCategory::OtherPunctuation
+            | GeneralCategory::OpenPunctuation
+            | GeneralCategory::ClosePunctuation
+            | GeneralCategory::InitialPunctuation
+            | GeneralCategory::FinalPunctuation
+            | GeneralCategory::ConnectorPunctuation
+            | GeneralCategory::DashPunctuation
+            | GeneralCategory::MathSymbol
+            | GeneralCategory::CurrencySymbol
+            | GeneralCategory::ModifierSymbol
+    )
+}
+
+/// Determine whether a character qualifies as (non-line-break)
+/// whitespace.
+#[inline]
+fn char_is_whitespace(ch: char) -> bool {
+    // TODO: this is a naive binary categorization of whitespace
+    // characters.  For display, word wrapping, etc. we'll need a better
+    // categorization based on e.g. breaking vs non-breaking spaces
+    // and whether they're zero-width or not.
+    //
+    // The ambiguous choices below can be overridden per character with the
+    // `separators` setting, see `TokenizerConfig::separators`.
+    match ch {
+        //'\u{1680}' | // Ogham Space Mark (here for completeness, but usually displayed as a dash, not as whitespace)
+        '\u{0009}' | // Character Tabulation
+        '\u{0020}' | // Space
+        '\u{00A0}' | // No-break Space
+        '\u{180E}' | // Mongolian Vowel Separator
+        '\u{202F}' | // Narrow No-break Space
+        '\u{205F}' | // Medium Mathematical Space
+        '\u{3000}' | // Ideographic Space
+        '\u{FEFF}'   // Zero Width No-break Space
+        => true,
+
+        // En Quad, Em Quad, En Space, Em Space, Three-per-em Space,
+        // Four-per-em Space, Six-per-em Space, Figure Space,
+        // Punctuation Space, Thin Space, Hair Space, Zero Width Space.
+        ch if ('\u{2000}' ..= '\u{200B}').contains(&ch) => true,
+
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apostrophes_between_letters_join_contractions() {
+        let config = TokenizerConfig {
+            apostrophe_words: true,
+            ..TokenizerConfig::default()
+        };
+
+        assert_eq!(split("don't stop", &config), ["don't", " ", "stop"]);
+        assert_eq!(split("it’s here", &config), ["it’s", " ", "here"]);
+        assert_eq!(split("'quoted' words", &config), ["'", "quoted", "'", " ", "words"]);
+        assert_eq!(split("‘it’ cats’", &config), ["‘", "it", "’", " ", "cats", "’"]);
+        assert_eq!(split("rock 'n' roll", &config), ["rock", " ", "'", "n", "'", " ", "roll"]);
+
+        let config = TokenizerConfig::default();
+        assert_eq!(split("don't", &config), ["don", "'", "t"]);
+        assert_eq!(split("it’s", &config), ["it", "’", "s"]);
+    }
+
+    #[test]
+    fn joiners_keep_emoji_sequences_in_one_token() {
+        let config = TokenizerConfig::default();
+
+        // Woman technologist: woman, zero width joiner, laptop
+        assert_eq!(split("a 👩\u{200D}💻 b", &config), ["a", " ", "👩\u{200D}💻", " ", "b"]);
+        // Red heart: heart and variation selector 16
+        assert_eq!(split("❤\u{FE0F}!", &config), ["❤\u{FE0F}", "!"]);
+
+        // Persian "I want", with a zero width non-joiner inside the word
+        assert_eq!(split("می\u{200C}خواهم", &config), ["می\u{200C}خواهم"]);
+        let config = TokenizerConfig { joiners: false, ..TokenizerConfig::default() };
+        assert_eq!(split("می\u{200C}خواهم", &config), ["می", "\u{200C}", "خواهم"]);
+    }
+
+    #[test]
+    fn boundaries_are_where_split_puts_them() {
+        let configs = [
+            TokenizerConfig::default(),
+            TokenizerConfig {
+                sigil_tokens: true,
+                apostrophe_words: true,
+                split_digits: true,
+                separators: [('\u{200B}', false), ('-', true)].into(),
+                ..TokenizerConfig::default()
+            },
+        ];
+        let texts = [
+            "a #tag @me don't abc123 x\u{200B}y",
+            "foo-bar  it’s\r\nслово2word 👩\u{200D}💻!",
+            "ひらがなカタカナ漢字 #1 a_b",
+        ];
+
+        for config in &configs {
+            for text in texts {
+                let mut starts = Vec::new();
+                split_with(text, config, |start, _| starts.push(start));
+                for index in 0..=text.len() {
+                    let expected = index == text.len() || starts.contains(&index);
+                    assert_eq!(is_boundary(text, index, config), expected, "{text:?} at {index}");
+                }
+            }
+        }
+    }
+}
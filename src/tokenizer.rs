@@ -0,0 +1,46 @@
+use std::fmt::Debug;
+
+use crate::{
+    config::TokenizerConfig,
+    text::{is_word, split, split_words},
+};
+
+/// Splits buffer text into words.
+///
+/// The server uses [`CategoryTokenizer`] unless another implementation is given to the backend
+/// at construction, e.g. a dictionary-based segmenter for scripts that do not separate words with
+/// spaces, such as Thai or Japanese.
+pub trait Tokenizer: Debug + Send + Sync {
+    /// Split `text` into consecutive tokens covering all of it, including the whitespace and
+    /// punctuation between words.
+    fn split<'a>(&self, text: &'a str, config: &TokenizerConfig) -> Vec<&'a str>;
+
+    /// The words of `text` with their byte offset, leaving out whitespace, punctuation and other
+    /// tokens that are never completed. Used to build the index.
+    fn words<'a>(&self, text: &'a str, config: &TokenizerConfig) -> Vec<(usize, &'a str)> {
+        let mut offset = 0;
+
+        self.split(text, config)
+            .into_iter()
+            .filter_map(|token| {
+                let start = offset;
+                offset += token.len();
+                is_word(token, config).then_some((start, token))
+            })
+            .collect()
+    }
+}
+
+/// The default tokenizer, which splits text wherever the category of the characters changes.
+#[derive(Debug, Default)]
+pub struct CategoryTokenizer;
+
+impl Tokenizer for CategoryTokenizer {
+    fn split<'a>(&self, text: &'a str, config: &TokenizerConfig) -> Vec<&'a str> {
+        split(text, config)
+    }
+
+    fn words<'a>(&self, text: &'a str, config: &TokenizerConfig) -> Vec<(usize, &'a str)> {
+        split_words(text, config)
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::LN_2,
+};
+
+use crate::{config::TokenizerConfig, tokenizer::Tokenizer};
+
+/// Most words kept in the vocabulary. The lowest ranked ones are forgotten beyond it.
+const VOCABULARY_SIZE_LIMIT: usize = 100_000;
+
+/// Words seen in every document over the session, for
+/// [`Config::global_vocabulary`](crate::config::Config::global_vocabulary).
+///
+/// A word weighs its number of occurrences when seen, halved every `half_life` changes of any
+/// document it goes without being seen again.
+#[derive(Debug, Default)]
+pub struct Vocabulary {
+    /// Weight of each word when last seen, with the clock at that time.
+    words: HashMap<String, (f64, u64)>,
+    /// The words, heaviest first. Every weight decays at the same rate, so only seeing words
+    /// changes their order.
+    ranked: Vec<String>,
+    /// Number of changes of any document so far.
+    clock: u64,
+}
+
+impl Vocabulary {
+    /// Record a change of a document, decaying every weight.
+    pub fn tick(&mut self) {
+        self.clock += 1;
+    }
+
+    /// Add the occurrences of the words of `text` to their weight.
+    pub fn see(
+        &mut self,
+        text: &str,
+        tokenizer: &dyn Tokenizer,
+        config: &TokenizerConfig,
+        half_life: u64,
+    ) {
+        let mut counts = HashMap::<&str, usize>::new();
+        for (_, word) in tokenizer.words(text, config) {
+            *counts.entry(word).or_default() += 1;
+        }
+
+        for (word, count) in counts {
+            let (weight, seen) = self.words.entry(word.to_string()).or_insert((0.0, self.clock));
+            *weight = *weight * log_decay(self.clock - *seen, half_life).exp() + count as f64;
+            *seen = self.clock;
+        }
+
+        // Comparing the logarithms of the weights as of the start of the session gives the same
+        // order as now, without decaying every weight to the current clock or overflowing
+        let initial = |word: &String| {
+            let (weight, seen) = self.words[word];
+            weight.ln() - log_decay(seen, half_life)
+        };
+        let mut ranked = self.words.keys().cloned().collect::<Vec<_>>();
+        ranked.sort_by(|a, b| initial(b).total_cmp(&initial(a)).then_with(|| a.cmp(b)));
+        ranked.truncate(VOCABULARY_SIZE_LIMIT);
+        if ranked.len() < self.words.len() {
+            let kept = ranked.iter().collect::<HashSet<_>>();
+            self.words.retain(|word, _| kept.contains(word));
+        }
+
+        self.ranked = ranked;
+    }
+
+    /// The words, heaviest first.
+    pub fn ranked(&self) -> &[String] {
+        &self.ranked
+    }
+}
+
+/// Logarithm of what a weight is multiplied by after `elapsed` changes. Weights never decay when
+/// `half_life` is `0`.
+fn log_decay(elapsed: u64, half_life: u64) -> f64 {
+    if half_life == 0 {
+        return 0.0;
+    }
+
+    -LN_2 * elapsed as f64 / half_life as f64
+}
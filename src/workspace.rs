@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{Url, WorkspaceFolder};
+
+/// Maximum number of files read from the workspace.
+const WORKSPACE_FILE_LIMIT: usize = 1000;
+
+/// Files larger than this, in bytes, are skipped.
+const WORKSPACE_FILE_SIZE_LIMIT: u64 = 1024 * 1024;
+
+/// A file read from the workspace without being opened by the client.
+#[derive(Debug)]
+pub struct WorkspaceFile {
+    pub uri: Url,
+    /// File name, shown as the source of its completions.
+    pub name: String,
+    pub text: String,
+}
+
+/// The files under `roots` to read for
+/// [`Config::complete_from_workspace`](crate::config::Config::complete_from_workspace).
+///
+/// Hidden files and directories, such as `.git`, are skipped, as are files that are too large.
+/// At most [`WORKSPACE_FILE_LIMIT`] files are listed, walking directories in name order so the
+/// same files are picked on every start.
+pub fn list_workspace(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = roots.iter().rev().cloned().collect::<Vec<_>>();
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries = entries
+            .flatten()
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let mut subdirectories = Vec::new();
+        for entry in entries {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                subdirectories.push(entry.path());
+                continue;
+            }
+            if !metadata.is_file() || metadata.len() > WORKSPACE_FILE_SIZE_LIMIT {
+                continue;
+            }
+
+            files.push(entry.path());
+            if files.len() == WORKSPACE_FILE_LIMIT {
+                return files;
+            }
+        }
+
+        pending.extend(subdirectories.into_iter().rev());
+    }
+
+    files
+}
+
+/// The innermost of `folders` containing `uri`.
+pub fn folder_of<'a>(folders: &'a [WorkspaceFolder], uri: &Url) -> Option<&'a WorkspaceFolder> {
+    folders
+        .iter()
+        .filter(|folder| {
+            let root = folder.uri.as_str().trim_end_matches('/');
+            uri.as_str()
+                .strip_prefix(root)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+        .max_by_key(|folder| folder.uri.as_str().len())
+}
+
+/// Read the file at `path`, unless it is not text.
+pub fn read_workspace_file(path: &Path) -> Option<WorkspaceFile> {
+    let text = std::fs::read_to_string(path).ok()?;
+    if text.contains('\0') {
+        return None;
+    }
+
+    Some(WorkspaceFile {
+        uri: Url::from_file_path(path).ok()?,
+        name: path.file_name()?.to_string_lossy().into_owned(),
+        text,
+    })
+}